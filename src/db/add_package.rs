@@ -22,9 +22,11 @@ use failure::err_msg;
 
 /// Adds a package into database.
 ///
-/// Package must be built first.
+/// Package must be built first, `metadata` is the `[package.metadata.docs.rs]` table it was
+/// built with.
 pub fn add_package_into_database(conn: &Connection,
                                  pkg: &Package,
+                                 metadata: &Metadata,
                                  res: &ChrootBuilderResult,
                                  files: Option<Json>,
                                  doc_targets: Vec<String>)
@@ -39,8 +41,6 @@ pub fn add_package_into_database(conn: &Connection,
         &TargetKind::Lib(_) => true,
         _ => false,
     };
-    let metadata = Metadata::from_package(pkg)?;
-
     let release_id: i32 = {
         let rows = try!(conn.query("SELECT id FROM releases WHERE crate_id = $1 AND version = $2",
                                    &[&crate_id, &format!("{}", pkg.manifest().version())]));
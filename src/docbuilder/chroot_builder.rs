@@ -80,7 +80,24 @@ impl DocBuilder {
 
         // get_package (and cargo) is using semver, add '=' in front of version.
         let pkg = try!(get_package(name, Some(&format!("={}", version)[..])));
-        let metadata = Metadata::from_package(&pkg)?;
+        let metadata = Metadata::from_package(&pkg).and_then(|m| m.validate_build().map(|_| m));
+        let metadata = match metadata {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                warn!("Invalid docs.rs metadata in {}-{}: {}", name, version, err);
+                let output = format!("Invalid [package.metadata.docs.rs]: {}", err);
+                try!(self.add_unbuilt_package_into_database(&conn, &pkg, output));
+                self.cache.insert(format!("{}-{}", name, version));
+                return Ok(false);
+            }
+        };
+
+        if !metadata.build {
+            info!("Skipping package {}-{}, builds are disabled in its metadata", name, version);
+            self.cache.insert(format!("{}-{}", name, version));
+            return Ok(false);
+        }
+
         let res = self.build_package_in_chroot(&pkg, metadata.default_target.clone());
 
         // copy sources and documentation
@@ -102,6 +119,7 @@ impl DocBuilder {
 
         let release_id = try!(add_package_into_database(&conn,
                                                         &pkg,
+                                                        &metadata,
                                                         &res,
                                                         Some(file_list),
                                                         successfully_targets));
@@ -313,6 +331,37 @@ impl DocBuilder {
     }
 
 
+    /// Adds a package which isn't built into database, with a failed build carrying `output`.
+    ///
+    /// This is used when the metadata of the package is invalid, so the release is recorded
+    /// with the default metadata.
+    fn add_unbuilt_package_into_database(&self,
+                                         conn: &Connection,
+                                         package: &Package,
+                                         output: String)
+                                         -> Result<()> {
+        let (rustc_version, cratesfyi_version) = self.get_versions();
+        let res = ChrootBuilderResult {
+            output: output,
+            build_success: false,
+            have_doc: false,
+            have_examples: self.have_examples(&package),
+            rustc_version: rustc_version,
+            cratesfyi_version: cratesfyi_version,
+        };
+        info!("Adding {}-{} into database with the default docs.rs metadata",
+              package.name(), package.version());
+        let release_id = try!(add_package_into_database(conn,
+                                                        package,
+                                                        &Metadata::default(),
+                                                        &res,
+                                                        None,
+                                                        Vec::new()));
+        try!(add_build_into_database(conn, &release_id, &res));
+        Ok(())
+    }
+
+
     /// Adds sources into database
     fn add_sources_into_database(&self, conn: &Connection, package: &Package) -> Result<Json> {
         debug!("Adding sources into database");
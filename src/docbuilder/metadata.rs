@@ -1,9 +1,178 @@
-
-use std::path::Path;
+use std::path::{Path, PathBuf, Component};
+use std::io::Read;
+use std::env;
+use std::str;
+use std::fmt;
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::Duration;
+use std::result::Result as StdResult;
+use std::error::Error as StdError;
 use cargo::core::Package;
+use toml;
 use toml::Value;
+use toml::value::Table;
+use serde_json;
 use error::Result;
 use failure::err_msg;
+use url::Url;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{Error as DeError, IgnoredAny};
+
+
+/// Target docs.rs is running on
+pub const HOST_TARGET: &'static str = "x86_64-unknown-linux-gnu";
+
+
+/// Longest `build-timeout` docs.rs allows, in seconds
+pub const MAX_BUILD_TIMEOUT: u64 = 2 * 60 * 60;
+
+
+/// Most entries docs.rs allows in a list like `features` or `rustc-args`
+pub const MAX_LIST_LENGTH: usize = 256;
+
+
+/// Longest total length of the entries docs.rs allows in a list like `features` or
+/// `rustc-args`, in bytes
+pub const MAX_LIST_BYTES: usize = 16 * 1024;
+
+
+/// Targets known to be supported by Rust
+///
+/// Targets missing from this list are only reported with a warning, so a target added to Rust
+/// later can be used before this list is updated.
+pub const KNOWN_TARGETS: &'static [&'static str] = &[
+    "aarch64-apple-ios",
+    "aarch64-linux-android",
+    "aarch64-pc-windows-msvc",
+    "aarch64-unknown-linux-gnu",
+    "aarch64-unknown-linux-musl",
+    "arm-linux-androideabi",
+    "arm-unknown-linux-gnueabi",
+    "arm-unknown-linux-gnueabihf",
+    "armv7-linux-androideabi",
+    "armv7-unknown-linux-gnueabihf",
+    "asmjs-unknown-emscripten",
+    "i586-pc-windows-msvc",
+    "i586-unknown-linux-gnu",
+    "i686-apple-darwin",
+    "i686-linux-android",
+    "i686-pc-windows-gnu",
+    "i686-pc-windows-msvc",
+    "i686-unknown-freebsd",
+    "i686-unknown-linux-gnu",
+    "i686-unknown-linux-musl",
+    "mips-unknown-linux-gnu",
+    "mips64-unknown-linux-gnuabi64",
+    "mipsel-unknown-linux-gnu",
+    "powerpc-unknown-linux-gnu",
+    "powerpc64-unknown-linux-gnu",
+    "powerpc64le-unknown-linux-gnu",
+    "riscv32imac-unknown-none-elf",
+    "s390x-unknown-linux-gnu",
+    "sparc64-unknown-linux-gnu",
+    "thumbv6m-none-eabi",
+    "thumbv7em-none-eabi",
+    "thumbv7em-none-eabihf",
+    "thumbv7m-none-eabi",
+    "wasm32-unknown-emscripten",
+    "wasm32-unknown-unknown",
+    "x86_64-apple-darwin",
+    "x86_64-apple-ios",
+    "x86_64-linux-android",
+    "x86_64-pc-windows-gnu",
+    "x86_64-pc-windows-msvc",
+    "x86_64-sun-solaris",
+    "x86_64-unknown-freebsd",
+    "x86_64-unknown-linux-gnu",
+    "x86_64-unknown-linux-musl",
+    "x86_64-unknown-netbsd",
+    "x86_64-unknown-redox",
+];
+
+
+/// Keys docs.rs supported before it validated metadata.
+///
+/// Problems of these keys only cause warnings in `validate_build`, so crates which built before
+/// aren't rejected.
+const LEGACY_KEYS: &'static [&'static str] = &[
+    "features", "all-features", "no-default-features", "default-target", "rustc-args",
+    "rustdoc-args", "dependencies",
+];
+
+
+/// Lowest `priority` of a crate in the build queue
+pub const MIN_PRIORITY: i32 = -100;
+
+/// Highest `priority` of a crate in the build queue
+pub const MAX_PRIORITY: i32 = 100;
+
+
+/// Largest `memory-limit` docs.rs allows, in megabytes
+pub const MAX_MEMORY_LIMIT: u64 = 8 * 1024;
+
+/// Largest `cpu-quota` docs.rs allows, in CPUs
+pub const MAX_CPU_QUOTA: u32 = 4;
+
+
+/// `targets` value meaning every target supported by docs.rs
+const ALL_TARGETS: &'static str = "*";
+
+
+/// Version spec of system dependencies which are not pinned
+const ANY_VERSION: &'static str = "*";
+
+
+/// Release channels which can have their own arguments
+const CHANNELS: &'static [&'static str] = &["stable", "beta", "nightly"];
+
+
+/// Keys of argument lists, which are ignored as a whole if an entry is not a string
+const ARGS_KEYS: &'static [&'static str] = &["cargo-args", "rustc-args", "rustdoc-args"];
+
+
+/// Keys recognized in `[package.metadata.docs.rs]` table
+///
+/// Tables named after targets are recognized too, but they are not listed here.
+pub const KNOWN_KEYS: &'static [&'static str] = &[
+    "features",
+    "feature-matrix",
+    "all-features",
+    "features-doc",
+    "no-default-features",
+    "default-features",
+    "no-deps",
+    "exclude-deps",
+    "default-target",
+    "targets",
+    "exclude-targets",
+    "cargo-args",
+    "rustc-args",
+    "rustdoc-args",
+    "cfg",
+    "docsrs-cfg",
+    "document-private-items",
+    "document-examples",
+    "rustdoc-header",
+    "rustdoc-map",
+    "documentation",
+    "readme",
+    "extra-files",
+    "dependencies",
+    "dependencies-map",
+    "env",
+    "toolchain",
+    "rust-version",
+    "build",
+    "build-timeout",
+    "max-output-size",
+    "stable",
+    "beta",
+    "nightly",
+    "priority",
+    "memory-limit",
+    "cpu-quota",
+];
 
 /// Metadata for custom builds
 ///
@@ -18,169 +187,6118 @@ use failure::err_msg;
 ///
 /// [package.metadata.docs.rs]
 /// features = [ "feature1", "feature2" ]
-/// all-features = true
+/// feature-matrix = [ [ "feature1" ], [ "feature2" ] ]
+/// all-features = false
+/// features-doc = "doc-all"
 /// no-default-features = true
+/// no-deps = true
+/// exclude-deps = [ "openssl-sys", "winapi" ]
 /// default-target = "x86_64-unknown-linux-gnu"
+/// targets = [ "x86_64-apple-darwin", "x86_64-pc-windows-msvc" ]
+/// cargo-args = [ "-Zbuild-std" ]
 /// rustc-args = [ "--example-rustc-arg" ]
 /// rustdoc-args = [ "--example-rustdoc-arg" ]
+/// cfg = [ "example_cfg" ]
+/// docsrs-cfg = true
+/// document-private-items = false
+/// document-examples = false
+/// rustdoc-header = "docs/header.html"
+/// documentation = "https://example.com/docs"
+/// readme = "docs/README.md"
+/// extra-files = [ "docs/diagram.svg" ]
 /// dependencies = [ "example-system-dependency" ]
+/// toolchain = "nightly-2019-02-01"
+/// build = true
+/// build-timeout = "15min"
+/// max-output-size = "500MB"
+/// priority = 0
+/// memory-limit = 4096
+/// cpu-quota = 2
+///
+/// [package.metadata.docs.rs.env]
+/// EXAMPLE_ENV_VAR = "example-value"
+///
+/// [package.metadata.docs.rs.rustdoc-map]
+/// example-dependency = "https://example.com/docs/"
 /// ```
 ///
 /// You can define one or more fields in your `Cargo.toml`.
+// Fields are deserialized by an inherent `Metadata::deserialize`, which `from_table` uses. The
+// `Deserialize` trait is implemented with `from_table`, see below.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(remote = "Metadata", default, rename_all = "kebab-case")]
 pub struct Metadata {
     /// List of features docs.rs will build.
     ///
     /// By default, docs.rs will only build default features.
+    ///
+    /// An empty list is kept as `Some(vec![])`, it's not the same as a missing list. Together
+    /// with `no-default-features = true` it builds the crate without any feature.
+    ///
+    /// Features can also be defined for each target with a table:
+    ///
+    /// ```text
+    /// [package.metadata.docs.rs.features]
+    /// x86_64-unknown-linux-gnu = [ "tokio" ]
+    /// wasm32-unknown-unknown = []
+    /// ```
+    #[serde(deserialize_with = "deserialize_features")]
     pub features: Option<Vec<String>>,
 
+    /// Features of each target defined with `[package.metadata.docs.rs.features]` table.
+    #[serde(skip)]
+    pub target_features: BTreeMap<String, Vec<String>>,
+
+    /// List of feature sets. Docs.rs will produce a separate documentation build for each set.
+    pub feature_matrix: Option<Vec<Vec<String>>>,
+
     /// Set `all-features` to true if you want docs.rs to build all features for your crate
+    ///
+    /// This can't be combined with a non-empty `features` list.
     pub all_features: bool,
 
+    /// Name of a feature which enables everything that should be documented.
+    ///
+    /// Crates with mutually exclusive features can't be built with `all-features`. When this is
+    /// set, docs.rs builds only this feature with `--no-default-features`, and `all-features`
+    /// is ignored.
+    pub features_doc: Option<String>,
+
     /// Docs.rs will always build default features.
     ///
-    /// Set `no-default-fatures` to `false` if you want to build only certain features.
+    /// Set `no-default-features` to `true` if you want to build only certain features.
     pub no_default_features: bool,
 
+    /// `default-features = false` is the same as `no-default-features = true`.
+    ///
+    /// Setting both keys to contradicting values is an error.
+    pub default_features: Option<bool>,
+
+    /// Set `no-deps` to true if you want docs.rs to document only your crate, and not its
+    /// dependencies (`cargo doc --no-deps`).
+    pub no_deps: bool,
+
+    /// Set `document-private-items` to true if you want docs.rs to document private items too
+    /// (`rustdoc --document-private-items`).
+    ///
+    /// This can make the documentation of a crate substantially larger.
+    pub document_private_items: bool,
+
+    /// Set `document-examples` to true if you want docs.rs to document your examples too
+    /// (`cargo doc --examples`).
+    ///
+    /// This only affects examples which actually exist in the crate, and it's independent from
+    /// `no-deps`.
+    pub document_examples: bool,
+
+    /// Dependencies which shouldn't be documented.
+    ///
+    /// This is ignored if `no-deps` is set, since no dependency is documented then.
+    pub exclude_deps: Option<Vec<String>>,
+
     /// Docs.rs is running on `x86_64-unknown-linux-gnu` target system and default documentation
     /// is always built on this target. You can change default target by setting this.
     pub default_target: Option<String>,
 
+    /// List of targets docs.rs will build documentation for.
+    ///
+    /// If `default-target` is also set, it should be one of these targets, otherwise it's built
+    /// in addition to them with a warning. It is treated as the primary target and moved to the
+    /// beginning of this list.
+    ///
+    /// Set `targets = "*"` to build documentation for every target supported by docs.rs. The
+    /// wildcard can't be combined with other targets.
+    ///
+    /// Targets can also be defined as tables to override some settings for a specific target:
+    ///
+    /// ```text
+    /// [package.metadata.docs.rs.targets.wasm32-unknown-unknown]
+    /// features = [ "wasm" ]
+    /// ```
+    ///
+    /// A table named after a `cfg(...)` expression, like in Cargo's platform specific
+    /// dependencies, overrides settings of every target matching the expression:
+    ///
+    /// ```text
+    /// [package.metadata.docs.rs.targets.'cfg(windows)']
+    /// features = [ "winapi" ]
+    /// ```
+    #[serde(deserialize_with = "deserialize_targets")]
+    pub targets: Option<Vec<String>>,
+
+    /// Per-target overrides defined in `[package.metadata.docs.rs.targets.<target>]` tables.
+    #[serde(skip)]
+    pub target_overrides: HashMap<String, TargetMetadata>,
+
+    /// Overrides defined in `[package.metadata.docs.rs.targets.'cfg(...)']` tables, by
+    /// `cfg(...)` expression.
+    #[serde(skip)]
+    pub cfg_target_overrides: BTreeMap<String, TargetMetadata>,
+
+    /// Targets which are left out when `targets = "*"` is used.
+    ///
+    /// Targets which are not supported by docs.rs are ignored with a warning.
+    pub exclude_targets: Option<Vec<String>>,
+
+    /// List of command line arguments for `cargo`.
+    ///
+    /// These are passed verbatim to `cargo doc`, before `--target` and feature flags
+    /// (`--features`, `--all-features` and `--no-default-features`) and before the crate-specific
+    /// `rustc-args` and `rustdoc-args`.
+    pub cargo_args: Option<Vec<String>>,
+
     /// List of command line arguments for `rustc`.
+    #[serde(deserialize_with = "deserialize_string_or_array")]
     pub rustc_args: Option<Vec<String>>,
 
     /// List of command line arguments for `rustdoc`.
+    #[serde(deserialize_with = "deserialize_string_or_array")]
     pub rustdoc_args: Option<Vec<String>>,
 
+    /// Additional `--cfg` values for `rustdoc`, `docsrs` is set unless `docsrs-cfg` is false.
+    ///
+    /// This can be a single value like `cfg = "custom_flag"` or a list of values.
+    #[serde(deserialize_with = "deserialize_string_or_array")]
+    pub cfg: Option<Vec<String>>,
+
+    /// Set `docsrs-cfg` to `false` if your crate must not be documented with `--cfg docsrs`.
+    pub docsrs_cfg: bool,
+
+    /// Additional `rustdoc` arguments for a specific target.
+    ///
+    /// These are defined in a table named after the target and appended to `rustdoc-args`:
+    ///
+    /// ```text
+    /// [package.metadata.docs.rs.'wasm32-unknown-unknown']
+    /// rustdoc-args = [ "--html-in-header", "wasm.html" ]
+    /// ```
+    ///
+    /// `features` and `rustc-args` of these tables are added to `target_overrides`, and tables
+    /// named after a `cfg(...)` expression to `cfg_target_overrides`. Tables which are not named
+    /// after a target are unknown keys.
+    #[serde(skip)]
+    pub per_target_rustdoc_args: BTreeMap<String, Vec<String>>,
+
+    /// Additional `rustc` and `rustdoc` arguments for a release channel.
+    ///
+    /// These are defined in `stable`, `beta` or `nightly` tables and appended to `rustc-args`
+    /// and `rustdoc-args` when documentation is built with that channel:
+    ///
+    /// ```text
+    /// [package.metadata.docs.rs.nightly]
+    /// rustdoc-args = [ "--cfg", "docsrs_nightly" ]
+    /// ```
+    #[serde(skip)]
+    pub channel_args: BTreeMap<String, ChannelMetadata>,
+
+    /// Path of a HTML file, relative to the crate root, which is included in the `<head>` of
+    /// every documentation page with `--html-in-header`.
+    pub rustdoc_header: Option<String>,
+
+    /// Path of a README, relative to the crate root, which docs.rs shows as the front page of
+    /// the crate instead of the `readme` of the manifest.
+    pub readme: Option<String>,
+
+    /// Paths of files, relative to the crate root, which are copied into the root of the
+    /// documentation output directory, like diagrams referenced from the documentation.
+    pub extra_files: Option<Vec<String>>,
+
+    /// Documentation URLs of dependencies which are not hosted on docs.rs.
+    ///
+    /// Each entry is passed to `rustdoc` as `--extern-html-root-url name=URL` after
+    /// `rustdoc-args`. Entries without a valid http(s) URL are ignored with a warning.
+    pub rustdoc_map: Option<BTreeMap<String, String>>,
+
+    /// URL of the canonical documentation of the crate, hosted outside of docs.rs.
+    ///
+    /// This is the `documentation` key of the metadata table, which is distinct from the
+    /// `documentation` of the manifest, and it must be an http(s) URL.
+    #[serde(rename = "documentation")]
+    pub documentation_url: Option<String>,
+
     /// System dependencies.
     ///
-    /// Docs.rs is running on a Debian jessie.
+    /// Docs.rs is running on a Debian jessie. Packages can be pinned to a version with a table:
+    ///
+    /// ```text
+    /// [package.metadata.docs.rs.dependencies]
+    /// libssl-dev = "1.1.*"
+    /// example-system-dependency = true
+    /// ```
+    #[serde(deserialize_with = "deserialize_dependencies")]
     pub dependencies: Option<Vec<String>>,
-}
 
+    /// Version specs of system dependencies.
+    ///
+    /// Unpinned packages, given in an array or as `true`, have `*` as their version spec.
+    #[serde(skip)]
+    pub dependency_specs: Option<HashMap<String, String>>,
 
+    /// System dependencies for each package manager.
+    ///
+    /// Package names differ between distributions, so they can be listed for each package
+    /// manager. `dependencies` is used for package managers which are not listed here.
+    ///
+    /// ```text
+    /// [package.metadata.docs.rs.dependencies-map]
+    /// apt = [ "libssl-dev" ]
+    /// apk = [ "openssl-dev" ]
+    /// ```
+    pub dependencies_map: Option<BTreeMap<String, Vec<String>>>,
 
-impl Metadata {
-    pub fn from_package(pkg: &Package) -> Result<Metadata> {
-        let src_path = pkg.manifest_path().parent().ok_or_else(|| err_msg("Source path not available"))?;
-        for c in ["Cargo.toml.orig", "Cargo.toml"].iter() {
-            let manifest_path = src_path.clone().join(c);
-            if manifest_path.exists() {
-                return Ok(Metadata::from_manifest(manifest_path));
-            }
-        }
-        Err(err_msg("Manifest not found"))
-    }
+    /// Environment variables set during the build.
+    ///
+    /// Values must be strings, a manifest with any other value is rejected. Variables which
+    /// configure the build itself, like `RUSTFLAGS`, `CARGO_*` or `LD_PRELOAD`, are rejected by
+    /// `sanitize_args`.
+    pub env: Option<BTreeMap<String, String>>,
+
+    /// Toolchain used to build documentation.
+    ///
+    /// This must be a channel name (`stable`, `beta` or `nightly`), optionally followed by a
+    /// date, like `nightly-2019-02-01`, or a Rust version, like `1.31` or `1.31.1`.
+    ///
+    /// A Rust version can also be given with `rust-version = "1.31"`. If both keys are set,
+    /// `toolchain` is used.
+    pub toolchain: Option<String>,
+
+    /// Set `build` to `false` if you don't want docs.rs to build documentation for your crate.
+    ///
+    /// A skipped build is logged with a skipped status and not recorded as a failed build.
+    pub build: bool,
+
+    /// Maximum duration of the documentation build.
+    ///
+    /// This can be an integer number of seconds, or a duration like `"15min"` or `"1h 30min"`.
+    /// Timeouts longer than `MAX_BUILD_TIMEOUT` are clamped, see `build_timeout_seconds`.
+    #[serde(deserialize_with = "deserialize_build_timeout")]
+    pub build_timeout: Option<Duration>,
+
+    /// `build-timeout` in seconds, clamped to `MAX_BUILD_TIMEOUT`.
+    ///
+    /// This is what the builder uses as the timeout of the build process. It's set when the
+    /// metadata is parsed or merged, a longer timeout is reported as a warning.
+    #[serde(skip)]
+    pub build_timeout_seconds: Option<u64>,
+
+    /// Largest size of the generated documentation in bytes, the build fails if the
+    /// documentation is larger.
+    ///
+    /// This can be an integer number of bytes, or a size like `"500MB"`. `KB`, `MB` and `GB` are
+    /// decimal units.
+    #[serde(deserialize_with = "deserialize_size")]
+    pub max_output_size: Option<u64>,
+
+    /// Priority of the crate in the build queue, higher priorities are built sooner.
+    ///
+    /// Priority must be between `MIN_PRIORITY` and `MAX_PRIORITY`, default is 0. Priorities
+    /// out of this range are clamped by `build_priority` and reported as a warning.
+    pub priority: Option<i64>,
+
+    /// Memory the build sandbox may use, in megabytes.
+    ///
+    /// This must be positive and at most `MAX_MEMORY_LIMIT`, the server default is used if it's
+    /// not set.
+    #[serde(rename = "memory-limit")]
+    pub memory_limit_mb: Option<u64>,
+
+    /// Number of CPUs the build sandbox may use.
+    ///
+    /// This must be positive and at most `MAX_CPU_QUOTA`, the server default is used if it's not
+    /// set.
+    pub cpu_quota: Option<i64>,
+
+    /// Keys found in the metadata table that docs.rs doesn't recognize.
+    ///
+    /// These are usually typos like `all-feature` and reported in the build log.
+    #[serde(flatten, deserialize_with = "deserialize_keys")]
+    pub unknown_keys: Vec<String>,
+
+    /// Keys which are given a single string instead of an array, like `features = "foo"`.
+    ///
+    /// These are accepted as one element lists and reported in the build log.
+    #[serde(skip)]
+    pub string_keys: Vec<String>,
+
+    /// Entries of lists like `features` which are not strings, with the key of the list.
+    ///
+    /// These are ignored and reported in the build log. Argument lists like `rustc-args` with
+    /// such an entry are ignored as a whole.
+    #[serde(skip)]
+    pub skipped_entries: Vec<(String, String)>,
+
+    /// Keys which are not written in kebab-case, like `all_features`, with their canonical
+    /// spelling.
+    ///
+    /// These are accepted and reported in the build log.
+    #[serde(skip)]
+    pub renamed_keys: Vec<(String, String)>,
+
+    /// The `[package.metadata.docs.rs]` table as it's written in the manifest.
+    ///
+    /// This gives access to keys which are not modelled by `Metadata` yet, like experimental
+    /// settings.
+    #[serde(skip)]
+    pub raw: Option<Table>,
+
+    /// Keys of the metadata table which are not mapped to a field, with their values.
+    ///
+    /// Deployments can read experimental settings from here without changing the parser. These
+    /// keys are also listed in `unknown_keys`. Unknown tables are target specific settings and
+    /// are not included.
+    #[serde(skip)]
+    pub extra: Table,
+
+    /// Known keys which are set in the manifest, `rust-version` is recorded as `toolchain`.
+    ///
+    /// This tells apart a value set to its default from a value which is not set at all.
+    #[serde(skip)]
+    set_keys: BTreeSet<String>,
+
+    /// Whether the manifest has a `[package.metadata.docs.rs]` table, even an empty one.
+    #[serde(skip)]
+    has_table: bool,
+}
 
-    pub fn from_manifest<P: AsRef<Path>>(path: P) -> Metadata {
-        use std::fs::File;
-        use std::io::Read;
-        let mut f = match File::open(path) {
-            Ok(f) => f,
-            Err(_) => return Metadata::default(),
-        };
-        let mut s = String::new();
-        if let Err(_) = f.read_to_string(&mut s) {
-            return Metadata::default();
-        }
-        Metadata::from_str(&s)
-    }
 
 
-    // This is similar to Default trait but it's private
+impl Default for Metadata {
     fn default() -> Metadata {
         Metadata {
             features: None,
+            target_features: BTreeMap::new(),
+            feature_matrix: None,
             all_features: false,
+            features_doc: None,
             no_default_features: false,
+            default_features: None,
+            no_deps: false,
+            document_private_items: false,
+            document_examples: false,
+            exclude_deps: None,
             default_target: None,
+            targets: None,
+            target_overrides: HashMap::new(),
+            cfg_target_overrides: BTreeMap::new(),
+            exclude_targets: None,
+            cargo_args: None,
             rustc_args: None,
             rustdoc_args: None,
+            cfg: None,
+            per_target_rustdoc_args: BTreeMap::new(),
+            channel_args: BTreeMap::new(),
+            rustdoc_header: None,
+            readme: None,
+            extra_files: None,
+            rustdoc_map: None,
+            documentation_url: None,
             dependencies: None,
+            dependency_specs: None,
+            dependencies_map: None,
+            env: None,
+            toolchain: None,
+            build: true,
+            docsrs_cfg: true,
+            build_timeout: None,
+            build_timeout_seconds: None,
+            max_output_size: None,
+            priority: None,
+            memory_limit_mb: None,
+            cpu_quota: None,
+            unknown_keys: Vec::new(),
+            string_keys: Vec::new(),
+            skipped_entries: Vec::new(),
+            renamed_keys: Vec::new(),
+            raw: None,
+            extra: Table::new(),
+            set_keys: BTreeSet::new(),
+            has_table: false,
         }
     }
+}
 
 
-    fn from_str(manifest: &str) -> Metadata {
-        let mut metadata = Metadata::default();
 
-        let manifest = match manifest.parse::<Value>() {
-            Ok(m) => m,
-            Err(_) => return metadata,
-        };
+/// Errors of reading metadata which callers may want to handle differently.
+///
+/// These are returned inside `error::Error` and can be recovered with `downcast_ref`.
+#[derive(Debug)]
+pub enum MetadataError {
+    /// Package manifest has no parent directory, and the current directory has no manifest.
+    SourcePathUnavailable,
+    /// Neither `Cargo.toml` nor `Cargo.toml.orig` exists in the source directory.
+    ManifestNotFound,
+    /// Manifest is not valid TOML or has invalid values.
+    Parse(toml::de::Error),
+    /// Target is not a well-formed target triple.
+    InvalidTarget(String),
+}
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MetadataError::SourcePathUnavailable => f.write_str("Source path not available"),
+            MetadataError::ManifestNotFound => f.write_str("Manifest not found"),
+            MetadataError::Parse(ref e) => write!(f, "Failed to parse manifest: {}", e),
+            MetadataError::InvalidTarget(ref target) => {
+                write!(f, "Invalid target triple: `{}`, expected `<arch>-<vendor>-<os>[-<abi>]`",
+                       target)
+            }
+        }
+    }
+}
+
+impl StdError for MetadataError {
+    fn source(&self) -> Option<&(StdError + 'static)> {
+        match *self {
+            MetadataError::Parse(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+
+/// Metadata is serialized like the `[package.metadata.docs.rs]` table, only fields which are set
+/// are serialized.
+impl Serialize for Metadata {
+    fn serialize<S: Serializer>(&self, serializer: S) -> StdResult<S::Ok, S::Error> {
+        self.to_toml()["package"]["metadata"]["docs"]["rs"].serialize(serializer)
+    }
+}
+
+
+/// Metadata is deserialized from a `[package.metadata.docs.rs]` table like `Metadata::from_str`
+/// reads it, so serialized metadata is read back as it was.
+impl<'de> Deserialize<'de> for Metadata {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> StdResult<Metadata, D::Error> {
+        let table = Table::deserialize(deserializer)?;
+        Metadata::from_table(table).map_err(D::Error::custom)
+    }
+}
+
+
+/// Metadata is compared by its settings. `raw`, `string_keys`, `skipped_entries` and
+/// `renamed_keys` only tell how the manifest was written and are not compared, so metadata read
+/// back from `to_toml` is equal to the original.
+impl PartialEq for Metadata {
+    fn eq(&self, other: &Metadata) -> bool {
+        let Metadata { ref features, ref target_features, ref feature_matrix, ref all_features,
+                       ref features_doc, ref no_default_features, ref default_features, ref no_deps,
+                       ref document_private_items, ref document_examples, ref exclude_deps,
+                       ref default_target, ref targets, ref target_overrides,
+                       ref cfg_target_overrides, ref exclude_targets, ref cargo_args,
+                       ref rustc_args, ref rustdoc_args, ref cfg, ref docsrs_cfg,
+                       ref per_target_rustdoc_args, ref channel_args, ref rustdoc_header,
+                       ref readme, ref extra_files, ref rustdoc_map, ref documentation_url,
+                       ref dependencies, ref dependency_specs, ref dependencies_map, ref env,
+                       ref toolchain, ref build, ref build_timeout, ref build_timeout_seconds,
+                       ref max_output_size, ref priority, ref memory_limit_mb, ref cpu_quota,
+                       ref unknown_keys, string_keys: _, skipped_entries: _, renamed_keys: _,
+                       raw: _, ref extra, ref set_keys, ref has_table } = *self;
+        *features == other.features &&
+            *target_features == other.target_features &&
+            *feature_matrix == other.feature_matrix &&
+            *all_features == other.all_features &&
+            *features_doc == other.features_doc &&
+            *no_default_features == other.no_default_features &&
+            *default_features == other.default_features &&
+            *no_deps == other.no_deps &&
+            *document_private_items == other.document_private_items &&
+            *document_examples == other.document_examples &&
+            *exclude_deps == other.exclude_deps &&
+            *default_target == other.default_target &&
+            *targets == other.targets &&
+            *target_overrides == other.target_overrides &&
+            *cfg_target_overrides == other.cfg_target_overrides &&
+            *exclude_targets == other.exclude_targets &&
+            *cargo_args == other.cargo_args &&
+            *rustc_args == other.rustc_args &&
+            *rustdoc_args == other.rustdoc_args &&
+            *cfg == other.cfg &&
+            *docsrs_cfg == other.docsrs_cfg &&
+            *per_target_rustdoc_args == other.per_target_rustdoc_args &&
+            *channel_args == other.channel_args &&
+            *rustdoc_header == other.rustdoc_header &&
+            *readme == other.readme &&
+            *extra_files == other.extra_files &&
+            *rustdoc_map == other.rustdoc_map &&
+            *documentation_url == other.documentation_url &&
+            *dependencies == other.dependencies &&
+            *dependency_specs == other.dependency_specs &&
+            *dependencies_map == other.dependencies_map &&
+            *env == other.env &&
+            *toolchain == other.toolchain &&
+            *build == other.build &&
+            *build_timeout == other.build_timeout &&
+            *build_timeout_seconds == other.build_timeout_seconds &&
+            *max_output_size == other.max_output_size &&
+            *priority == other.priority &&
+            *memory_limit_mb == other.memory_limit_mb &&
+            *cpu_quota == other.cpu_quota &&
+            *unknown_keys == other.unknown_keys &&
+            *extra == other.extra &&
+            *set_keys == other.set_keys &&
+            *has_table == other.has_table
+    }
+}
+
+
+/// A problem found in `[package.metadata.docs.rs]` table which doesn't stop the build.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataWarning {
+    /// Key is not recognized by docs.rs, `suggestion` is the closest known key.
+    UnknownKey { key: String, suggestion: Option<String> },
+    /// Setting has no effect because of another setting.
+    Redundant { reason: String },
+    /// Key is deprecated and `replacement` should be used instead.
+    Deprecated { key: String, replacement: String },
+    /// Value of `key` is invalid and ignored.
+    Invalid { key: String, reason: String },
+    /// Value of `key` is out of the range docs.rs allows and clamped into it.
+    Clamped { key: String, reason: String },
+}
+
+impl fmt::Display for MetadataWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MetadataWarning::UnknownKey { ref key, suggestion: Some(ref suggestion) } => {
+                write!(f, "unknown key '{}'; did you mean '{}'?", key, suggestion)
+            }
+            MetadataWarning::UnknownKey { ref key, suggestion: None } => {
+                write!(f, "unknown key '{}'", key)
+            }
+            MetadataWarning::Redundant { ref reason } |
+            MetadataWarning::Invalid { ref reason, .. } |
+            MetadataWarning::Clamped { ref reason, .. } => f.write_str(reason),
+            MetadataWarning::Deprecated { ref key, ref replacement } => {
+                write!(f, "'{}' is deprecated, use '{}' instead", key, replacement)
+            }
+        }
+    }
+}
+
+
+/// A setting which differs between two metadata tables, see `Metadata::diff`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    /// Key of the setting, like `all-features`.
+    pub field: String,
+    /// Old value rendered as TOML, `None` if it wasn't set.
+    pub old: Option<String>,
+    /// New value rendered as TOML, `None` if it isn't set anymore.
+    pub new: Option<String>,
+}
+
+impl fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.field,
+               self.old.as_ref().map_or("not set", |s| s.as_str()),
+               self.new.as_ref().map_or("not set", |s| s.as_str()))
+    }
+}
+
+
+/// An entry of `features`, in one of the syntaxes Cargo accepts
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeatureRef {
+    /// A feature of the crate, like `feature`.
+    Feature(String),
+    /// An optional dependency, like `dep:serde`.
+    Dependency(String),
+    /// A feature of a dependency, like `serde/derive`. `weak` is set for `serde?/derive`,
+    /// which doesn't enable the dependency if it's optional.
+    DependencyFeature { dependency: String, feature: String, weak: bool },
+}
+
+
+/// Arguments which are only used with a specific release channel
+#[derive(Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ChannelMetadata {
+    /// List of command line arguments for `rustc` for this channel.
+    pub rustc_args: Option<Vec<String>>,
+
+    /// List of command line arguments for `rustdoc` for this channel.
+    pub rustdoc_args: Option<Vec<String>>,
+}
+
+
+/// Settings which can be overridden for a specific target
+#[derive(Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct TargetMetadata {
+    /// List of features docs.rs will build for this target.
+    pub features: Option<Vec<String>>,
+
+    /// List of command line arguments for `rustc` for this target.
+    pub rustc_args: Option<Vec<String>>,
+
+    /// List of command line arguments for `rustdoc` for this target.
+    pub rustdoc_args: Option<Vec<String>>,
+}
+
+
+
+/// `Cargo.toml` wrapper used to deserialize `[package.metadata.docs.rs]` and
+/// `[workspace.metadata.docs.rs]` tables
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Manifest {
+    package: ManifestSection,
+    workspace: Option<ManifestWorkspace>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ManifestWorkspace {
+    members: Vec<String>,
+    exclude: Vec<String>,
+    metadata: ManifestMetadata,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ManifestSection {
+    name: Option<String>,
+    metadata: ManifestMetadata,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ManifestMetadata {
+    docs: ManifestDocs,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ManifestDocs {
+    rs: Option<Table>,
+}
+
+
+
+/// Returns the known key an unknown key is a spelling of, like `all-features` for
+/// `all_features` or `All-Features`.
+fn canonical_key(key: &str) -> Option<&'static str> {
+    let normalized = key.to_lowercase().replace('_', "-");
+    KNOWN_KEYS.iter().find(|k| **k == normalized).cloned()
+}
+
+
+/// Renames keys of a metadata table which are known keys in another spelling to their
+/// canonical kebab-case spelling.
+///
+/// Returns the renamed keys with their canonical spelling. Two spellings of the same key are
+/// an error.
+fn canonicalize_keys(table: &mut Table) -> Result<Vec<(String, String)>> {
+    let renamed: Vec<(String, &'static str)> = table.keys()
+        .filter(|k| !KNOWN_KEYS.contains(&k.as_str()))
+        .filter_map(|k| canonical_key(k).map(|canonical| (k.clone(), canonical)))
+        .collect();
+    for &(ref key, canonical) in &renamed {
+        if table.contains_key(canonical) {
+            return Err(format_err!("`{}` and `{}` are the same key, use `{}` only",
+                                   key, canonical, canonical));
+        }
+        let value = table.remove(key).unwrap();
+        table.insert(canonical.to_owned(), value);
+    }
+    Ok(renamed.into_iter().map(|(key, canonical)| (key, canonical.to_owned())).collect())
+}
+
+
+/// Removes entries which are not strings from lists of strings like `features`.
+///
+/// Lists in per-target tables, channel tables, the `features` table and `feature-matrix` are
+/// cleaned up too. Argument lists, see `ARGS_KEYS`, are removed as a whole instead, since
+/// dropping an entry could split a flag from its value. Returns the key, like
+/// `targets.wasm32-unknown-unknown.features`, and the first removed entry of argument lists or
+/// every removed entry of other lists, rendered as TOML.
+fn remove_non_string_entries(table: &mut Table) -> Vec<(String, String)> {
+    const LIST_KEYS: &'static [&'static str] = &[
+        "features", "exclude-deps", "targets", "exclude-targets", "cargo-args", "rustc-args",
+        "rustdoc-args", "cfg", "extra-files", "dependencies",
+    ];
+    const TARGET_LIST_KEYS: &'static [&'static str] = &["features", "rustc-args", "rustdoc-args"];
+
+    fn remove(key: String, value: &mut Value, removed: &mut Vec<(String, String)>) {
+        if let Value::Array(ref mut values) = *value {
+            for value in values.iter().filter(|v| !v.is_str()) {
+                removed.push((key.clone(), value.to_string()));
+            }
+            values.retain(|v| v.is_str());
+        }
+    }
 
-        if let Some(table) = manifest.get("package").and_then(|p| p.as_table())
-            .and_then(|p| p.get("metadata")).and_then(|p| p.as_table())
-                .and_then(|p| p.get("docs")).and_then(|p| p.as_table())
-                .and_then(|p| p.get("rs")).and_then(|p| p.as_table()) {
-                    metadata.features = table.get("features").and_then(|f| f.as_array())
-                        .and_then(|f| f.iter().map(|v| v.as_str().map(|v| v.to_owned())).collect());
-                    metadata.no_default_features = table.get("no-default-features")
-                        .and_then(|v| v.as_bool()).unwrap_or(metadata.no_default_features);
-                    metadata.all_features = table.get("all-features")
-                        .and_then(|v| v.as_bool()).unwrap_or(metadata.all_features);
-                    metadata.default_target = table.get("default-target")
-                        .and_then(|v| v.as_str()).map(|v| v.to_owned());
-                    metadata.rustc_args = table.get("rustc-args").and_then(|f| f.as_array())
-                        .and_then(|f| f.iter().map(|v| v.as_str().map(|v| v.to_owned())).collect());
-                    metadata.rustdoc_args = table.get("rustdoc-args").and_then(|f| f.as_array())
-                        .and_then(|f| f.iter().map(|v| v.as_str().map(|v| v.to_owned())).collect());
-                    metadata.dependencies = table.get("dependencies").and_then(|f| f.as_array())
-                        .and_then(|f| f.iter().map(|v| v.as_str().map(|v| v.to_owned())).collect());
+    fn remove_in_table(prefix: &str, keys: &[&str], table: &mut Table,
+                       removed: &mut Vec<(String, String)>) {
+        for key in keys {
+            let non_string = match table.get(*key) {
+                Some(&Value::Array(ref values)) if ARGS_KEYS.contains(key) => {
+                    values.iter().find(|v| !v.is_str()).map(|v| v.to_string())
                 }
+                _ => None,
+            };
+            if let Some(entry) = non_string {
+                table.remove(*key);
+                removed.push((format!("{}{}", prefix, key), entry));
+            } else if let Some(value) = table.get_mut(*key) {
+                remove(format!("{}{}", prefix, key), value, removed);
+            }
+        }
+    }
 
-        metadata
+    let mut removed = Vec::new();
+    remove_in_table("", LIST_KEYS, table, &mut removed);
+    for (key, value) in table.iter_mut() {
+        match (key.as_str(), value) {
+            ("features", &mut Value::Table(ref mut features)) => {
+                for (target, value) in features.iter_mut() {
+                    remove(format!("features.{}", target), value, &mut removed);
+                }
+            }
+            ("targets", &mut Value::Table(ref mut targets)) => {
+                for (target, value) in targets.iter_mut() {
+                    if let Value::Table(ref mut target_table) = *value {
+                        remove_in_table(&format!("targets.{}.", target), TARGET_LIST_KEYS,
+                                        target_table, &mut removed);
+                    }
+                }
+            }
+            ("feature-matrix", &mut Value::Array(ref mut matrix)) => {
+                for value in matrix.iter_mut() {
+                    remove("feature-matrix".to_owned(), value, &mut removed);
+                }
+            }
+            // channel and target tables
+            (key, &mut Value::Table(ref mut nested)) if !KNOWN_KEYS.contains(&key) ||
+                                                        CHANNELS.contains(&key) => {
+                remove_in_table(&format!("{}.", key), TARGET_LIST_KEYS, nested, &mut removed);
+            }
+            _ => {}
+        }
     }
+    removed
 }
 
 
+/// Returns the closest known key to an unknown key, if there is one within an edit distance of 2.
+fn suggest_key(unknown: &str) -> Option<&'static str> {
+    closest(unknown, KNOWN_KEYS, 2)
+}
 
-#[cfg(test)]
-mod test {
-    extern crate env_logger;
-    use super::Metadata;
 
-    #[test]
-    fn test_cratesfyi_metadata() {
-        let _ = env_logger::try_init();
-        let manifest = r#"
-            [package]
-            name = "test"
+/// Returns the closest known target to an unknown target, if there is one within an edit
+/// distance of 3.
+fn suggest_target(unknown: &str) -> Option<&'static str> {
+    closest(unknown, KNOWN_TARGETS, 3)
+}
 
-            [package.metadata.docs.rs]
-            features = [ "feature1", "feature2" ]
-            all-features = true
-            no-default-features = true
-            default-target = "x86_64-unknown-linux-gnu"
-            rustc-args = [ "--example-rustc-arg" ]
-            rustdoc-args = [ "--example-rustdoc-arg" ]
-            dependencies = [ "example-system-dependency" ]
-        "#;
 
-        let metadata = Metadata::from_str(manifest);
+/// Returns the closest candidate within `max_distance` edits.
+fn closest(unknown: &str, candidates: &[&'static str], max_distance: usize)
+           -> Option<&'static str> {
+    candidates.iter()
+        .map(|c| (levenshtein(unknown, c), *c))
+        .filter(|&(distance, _)| distance <= max_distance)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, c)| c)
+}
 
-        assert!(metadata.features.is_some());
-        assert!(metadata.all_features == true);
-        assert!(metadata.no_default_features == true);
-        assert!(metadata.default_target.is_some());
-        assert!(metadata.rustdoc_args.is_some());
 
-        let features = metadata.features.unwrap();
-        assert_eq!(features.len(), 2);
-        assert_eq!(features[0], "feature1".to_owned());
-        assert_eq!(features[1], "feature2".to_owned());
+/// Levenshtein distance between two strings
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..b.len() + 1).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                prev
+            } else {
+                1 + prev.min(cur).min(row[j])
+            };
+            prev = cur;
+        }
+    }
+    row[b.len()]
+}
 
-        assert_eq!(metadata.default_target.unwrap(), "x86_64-unknown-linux-gnu".to_owned());
 
-        let rustc_args = metadata.rustc_args.unwrap();
-        assert_eq!(rustc_args.len(), 1);
-        assert_eq!(rustc_args[0], "--example-rustc-arg".to_owned());
 
-        let rustdoc_args = metadata.rustdoc_args.unwrap();
-        assert_eq!(rustdoc_args.len(), 1);
-        assert_eq!(rustdoc_args[0], "--example-rustdoc-arg".to_owned());
+/// Deserializes a list of strings, a single string is accepted as a one element list.
+fn deserialize_string_or_array<'de, D>(deserializer: D)
+                                       -> StdResult<Option<Vec<String>>, D::Error>
+    where D: Deserializer<'de>
+{
+    match Value::deserialize(deserializer)? {
+        values @ Value::Array(_) => values.try_into().map(Some).map_err(D::Error::custom),
+        Value::String(value) => Ok(Some(vec![value])),
+        v => Err(D::Error::custom(format!("expected an array of strings, found {}", v))),
+    }
+}
 
-        let dependencies = metadata.dependencies.unwrap();
-        assert_eq!(dependencies.len(), 1);
-        assert_eq!(dependencies[0], "example-system-dependency".to_owned());
+
+
+/// Deserializes `features` either from an array of features or from a table of features for
+/// each target. Features of targets are read separately, so the table form gives `None`.
+fn deserialize_features<'de, D>(deserializer: D) -> StdResult<Option<Vec<String>>, D::Error>
+    where D: Deserializer<'de>
+{
+    match Value::deserialize(deserializer)? {
+        features @ Value::Array(_) => {
+            features.try_into()
+                .map(|features| Some(normalize_features(features)))
+                .map_err(D::Error::custom)
+        }
+        Value::String(feature) => Ok(Some(normalize_features(vec![feature]))),
+        Value::Table(_) => Ok(None),
+        _ => Err(D::Error::custom("expected an array of features or a table of targets")),
+    }
+}
+
+
+
+/// Trims whitespace around features and removes duplicates, keeping the first occurrence.
+///
+/// An empty list stays empty, so `features = []` is still distinguishable from a missing key.
+fn normalize_features(features: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::with_capacity(features.len());
+    for feature in features {
+        let feature = feature.trim();
+        if !normalized.iter().any(|f| f == feature) {
+            normalized.push(feature.to_owned());
+        }
+    }
+    normalized
+}
+
+
+
+/// Tells which kind of feature reference an entry of `features` is.
+fn classify_feature(s: &str) -> FeatureRef {
+    if s.starts_with("dep:") {
+        return FeatureRef::Dependency(s["dep:".len()..].to_owned());
+    }
+    match s.find('/') {
+        Some(pos) => {
+            let (dependency, feature) = (&s[..pos], &s[pos + 1..]);
+            let weak = dependency.ends_with('?');
+            FeatureRef::DependencyFeature {
+                dependency: dependency.trim_end_matches('?').to_owned(),
+                feature: feature.to_owned(),
+                weak: weak,
+            }
+        }
+        None => FeatureRef::Feature(s.to_owned()),
+    }
+}
+
+
+
+/// Matches `name` against a glob `pattern`, where `*` matches any number of characters.
+fn matches_glob(pattern: &str, name: &str) -> bool {
+    match pattern.find('*') {
+        Some(pos) => {
+            let (prefix, rest) = (&pattern[..pos], &pattern[pos + 1..]);
+            name.starts_with(prefix) &&
+                (prefix.len()..name.len() + 1)
+                    .filter(|&i| name.is_char_boundary(i))
+                    .any(|i| matches_glob(rest, &name[i..]))
+        }
+        None => pattern == name,
+    }
+}
+
+
+
+/// Keys of lists where the order of the entries has no effect on the build
+const UNORDERED_KEYS: &'static [&'static str] = &[
+    "features",
+    "exclude-deps",
+    "targets",
+    "exclude-targets",
+    "cfg",
+    "extra-files",
+    "dependencies",
+];
+
+
+
+/// Hashes bytes with 64-bit FNV-1a, which gives the same hash on every platform and rustc
+/// version.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+
+
+/// Returns `true` if a key of `targets` table is a `cfg(...)` expression instead of a target.
+fn is_cfg_expr(key: &str) -> bool {
+    key.starts_with("cfg(")
+}
+
+
+/// Checks a target is in `<arch>-<vendor>-<os>[-<abi>]` form.
+fn is_target_triple(target: &str) -> bool {
+    let components: Vec<&str> = target.split('-').collect();
+    components.len() >= 3 && components.len() <= 4 && components.iter().all(|c| !c.is_empty())
+}
+
+
+/// Checks an unknown table of `[package.metadata.docs.rs]` is named after a target: a known
+/// target, or a target triple which isn't a misspelling of a known key.
+fn is_target_table(key: &str) -> bool {
+    KNOWN_TARGETS.contains(&key) || (is_target_triple(key) && suggest_key(key).is_none())
+}
+
+
+/// A `cfg(...)` predicate, in the syntax of Cargo's platform specific dependencies
+#[derive(Debug, Clone, PartialEq)]
+enum CfgExpr {
+    Not(Box<CfgExpr>),
+    All(Vec<CfgExpr>),
+    Any(Vec<CfgExpr>),
+    Name(String),
+    KeyPair(String, String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum CfgToken {
+    Ident(String),
+    Str(String),
+    LeftParen,
+    RightParen,
+    Comma,
+    Equals,
+}
+
+impl CfgExpr {
+    /// Parses a `cfg(...)` expression, like `cfg(all(unix, target_arch = "x86_64"))`.
+    fn parse(expr: &str) -> StdResult<CfgExpr, String> {
+        let mut tokens = CfgExpr::tokenize(expr)?.into_iter().peekable();
+        match (tokens.next(), tokens.next()) {
+            (Some(CfgToken::Ident(ref cfg)), Some(CfgToken::LeftParen)) if cfg == "cfg" => {}
+            _ => return Err(format!("`{}` is not a cfg(...) expression", expr)),
+        }
+        let parsed = CfgExpr::parse_predicate(&mut tokens)?;
+        match (tokens.next(), tokens.next()) {
+            (Some(CfgToken::RightParen), None) => Ok(parsed),
+            _ => Err(format!("unexpected content after the predicate in `{}`", expr)),
+        }
+    }
+
+    fn tokenize(expr: &str) -> StdResult<Vec<CfgToken>, String> {
+        let mut tokens = Vec::new();
+        let mut chars = expr.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '(' => tokens.push(CfgToken::LeftParen),
+                ')' => tokens.push(CfgToken::RightParen),
+                ',' => tokens.push(CfgToken::Comma),
+                '=' => tokens.push(CfgToken::Equals),
+                '"' => {
+                    let mut value = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some(c) => value.push(c),
+                            None => return Err(format!("unterminated string in `{}`", expr)),
+                        }
+                    }
+                    tokens.push(CfgToken::Str(value));
+                }
+                c if c.is_whitespace() => {}
+                c if c.is_alphanumeric() || c == '_' => {
+                    let mut ident = c.to_string();
+                    while let Some(&c) = chars.peek() {
+                        if !c.is_alphanumeric() && c != '_' {
+                            break;
+                        }
+                        ident.push(c);
+                        chars.next();
+                    }
+                    tokens.push(CfgToken::Ident(ident));
+                }
+                c => return Err(format!("unexpected character `{}` in `{}`", c, expr)),
+            }
+        }
+        Ok(tokens)
+    }
+
+    fn parse_predicate<I>(tokens: &mut ::std::iter::Peekable<I>) -> StdResult<CfgExpr, String>
+        where I: Iterator<Item = CfgToken>
+    {
+        let name = match tokens.next() {
+            Some(CfgToken::Ident(name)) => name,
+            token => return Err(format!("expected a cfg name, found {:?}", token)),
+        };
+        match tokens.peek().cloned() {
+            Some(CfgToken::Equals) => {
+                tokens.next();
+                match tokens.next() {
+                    Some(CfgToken::Str(value)) => Ok(CfgExpr::KeyPair(name, value)),
+                    token => Err(format!("expected a string after `{} =`, found {:?}",
+                                         name, token)),
+                }
+            }
+            Some(CfgToken::LeftParen) => {
+                tokens.next();
+                let mut predicates = Vec::new();
+                loop {
+                    if tokens.peek() == Some(&CfgToken::RightParen) {
+                        tokens.next();
+                        break;
+                    }
+                    predicates.push(CfgExpr::parse_predicate(tokens)?);
+                    match tokens.next() {
+                        Some(CfgToken::Comma) => {}
+                        Some(CfgToken::RightParen) => break,
+                        token => return Err(format!("expected `,` or `)`, found {:?}", token)),
+                    }
+                }
+                match name.as_str() {
+                    "all" => Ok(CfgExpr::All(predicates)),
+                    "any" => Ok(CfgExpr::Any(predicates)),
+                    "not" if predicates.len() == 1 => {
+                        Ok(CfgExpr::Not(Box::new(predicates.remove(0))))
+                    }
+                    "not" => Err("not(...) takes exactly one predicate".to_owned()),
+                    _ => Err(format!("unknown cfg operator `{}`", name)),
+                }
+            }
+            _ => Ok(CfgExpr::Name(name)),
+        }
+    }
+
+    /// Returns `true` if the expression holds for a target triple.
+    fn matches(&self, target: &str) -> bool {
+        let info = TargetInfo::from_triple(target);
+        self.matches_info(&info)
+    }
+
+    fn matches_info(&self, info: &TargetInfo) -> bool {
+        match *self {
+            CfgExpr::Not(ref expr) => !expr.matches_info(info),
+            CfgExpr::All(ref exprs) => exprs.iter().all(|e| e.matches_info(info)),
+            CfgExpr::Any(ref exprs) => exprs.iter().any(|e| e.matches_info(info)),
+            CfgExpr::Name(ref name) => info.family == name.as_str(),
+            CfgExpr::KeyPair(ref key, ref value) => match key.as_str() {
+                "target_arch" => info.arch == value.as_str(),
+                "target_vendor" => info.vendor == value.as_str(),
+                "target_os" => info.os == value.as_str(),
+                "target_env" => info.env == value.as_str(),
+                "target_family" => info.family == value.as_str(),
+                _ => false,
+            },
+        }
+    }
+}
+
+
+/// The parts of a target triple which `cfg(...)` expressions can test
+struct TargetInfo<'a> {
+    arch: &'a str,
+    vendor: &'a str,
+    os: &'a str,
+    env: &'a str,
+    family: &'a str,
+}
+
+impl<'a> TargetInfo<'a> {
+    fn from_triple(target: &'a str) -> TargetInfo<'a> {
+        let parts: Vec<&str> = target.split('-').collect();
+        // triples like `arm-linux-androideabi` leave out the vendor
+        let (vendor, os, env) = match parts.len() {
+            3 if parts[1] == "linux" => ("unknown", parts[1], parts[2]),
+            n if n >= 3 => (parts[1], parts[2], parts.get(3).cloned().unwrap_or("")),
+            _ => ("unknown", parts.get(1).cloned().unwrap_or(""), ""),
+        };
+        let os = match os {
+            "darwin" => "macos",
+            "linux" if env.starts_with("android") => "android",
+            os => os,
+        };
+        let env = if env.starts_with("gnu") {
+            "gnu"
+        } else if env.starts_with("musl") {
+            "musl"
+        } else if env == "msvc" {
+            "msvc"
+        } else {
+            ""
+        };
+        let arch = match parts[0] {
+            "i386" | "i586" | "i686" => "x86",
+            "mipsel" => "mips",
+            "mips64el" => "mips64",
+            "powerpc64le" => "powerpc64",
+            arch if arch.starts_with("arm") || arch.starts_with("thumb") => "arm",
+            arch if arch.starts_with("riscv32") => "riscv32",
+            arch if arch.starts_with("riscv64") => "riscv64",
+            arch => arch,
+        };
+        let family = match os {
+            "windows" => "windows",
+            "linux" | "android" | "macos" | "ios" | "freebsd" | "netbsd" | "openbsd" |
+            "dragonfly" | "solaris" | "fuchsia" | "redox" | "emscripten" => "unix",
+            _ => "",
+        };
+        TargetInfo { arch: arch, vendor: vendor, os: os, env: env, family: family }
+    }
+}
+
+
+
+/// Deserializes `targets` either from an array of targets or from a table of target overrides
+fn deserialize_targets<'de, D>(deserializer: D) -> StdResult<Option<Vec<String>>, D::Error>
+    where D: Deserializer<'de>
+{
+    match Value::deserialize(deserializer)? {
+        Value::Array(targets) => {
+            let targets = targets.into_iter()
+                .map(|t| match t {
+                    Value::String(t) => Ok(t),
+                    t => Err(D::Error::custom(format!("invalid target: {}", t))),
+                })
+                .collect::<StdResult<Vec<String>, D::Error>>()?;
+            if targets.len() > 1 && targets.iter().any(|t| t == ALL_TARGETS) {
+                return Err(D::Error::custom("\"*\" can't be combined with other targets"));
+            }
+            Ok(Some(targets))
+        }
+        Value::Table(targets) => {
+            Ok(Some(targets.keys().filter(|t| !is_cfg_expr(t)).cloned().collect()))
+        }
+        Value::String(ref target) if target == ALL_TARGETS => {
+            Ok(Some(vec![ALL_TARGETS.to_owned()]))
+        }
+        _ => Err(D::Error::custom("expected \"*\", an array or a table of targets")),
+    }
+}
+
+
+
+/// Deserializes `dependencies` either from an array of packages or from a table of packages and
+/// their version specs.
+fn deserialize_dependencies<'de, D>(deserializer: D) -> StdResult<Option<Vec<String>>, D::Error>
+    where D: Deserializer<'de>
+{
+    match Value::deserialize(deserializer)? {
+        Value::Array(dependencies) => {
+            dependencies.into_iter()
+                .map(|d| match d {
+                    Value::String(d) => Ok(d),
+                    d => Err(D::Error::custom(format!("invalid dependency: {}", d))),
+                })
+                .collect::<StdResult<Vec<String>, D::Error>>()
+                .map(Some)
+        }
+        Value::Table(dependencies) => {
+            for (name, spec) in &dependencies {
+                match *spec {
+                    Value::String(_) | Value::Boolean(true) => {}
+                    _ => {
+                        return Err(D::Error::custom(format!("invalid version spec of {}: {}",
+                                                            name, spec)));
+                    }
+                }
+            }
+            Ok(Some(dependencies.keys().cloned().collect()))
+        }
+        Value::String(dependency) => Ok(Some(vec![dependency])),
+        _ => Err(D::Error::custom("expected an array or a table of dependencies")),
+    }
+}
+
+
+
+/// Flags which change where build outputs are written
+const DENIED_FLAGS: &'static [&'static str] = &[
+    "-o", "--out-dir", "--output", "--emit", "--target-dir",
+];
+
+
+/// Environment variables which configure cargo, rustc, rustdoc or the dynamic linker and can't
+/// be set in `env`. Names ending with `*` are prefixes.
+const RESERVED_ENV_VARS: &'static [&'static str] = &[
+    "CARGO*", "RUSTC*", "RUSTDOC*", "RUSTFLAGS", "RUSTUP*", "PATH", "HOME", "LD_*",
+];
+
+
+/// Checks an environment variable name is in `RESERVED_ENV_VARS`.
+fn is_reserved_env_var(name: &str) -> bool {
+    let name = name.to_uppercase();
+    RESERVED_ENV_VARS.iter().any(|reserved| if reserved.ends_with('*') {
+        name.starts_with(&reserved[..reserved.len() - 1])
+    } else {
+        name == *reserved
+    })
+}
+
+
+
+/// Returns the first argument which could write outside of the build directory or read
+/// libraries from an absolute path.
+///
+/// Arguments are split on whitespace, like `rustc-args` are split when they are passed in
+/// `RUSTFLAGS`. Long flags are matched with an `=` joined value, like `--out-dir=/tmp`, and
+/// short flags with an appended value, like `-o/tmp`.
+fn find_denied_arg(args: &[String]) -> Option<String> {
+    let mut args = args.iter().flat_map(|arg| arg.split_whitespace()).peekable();
+    while let Some(arg) = args.next() {
+        let denied = DENIED_FLAGS.iter().any(|flag| if flag.starts_with("--") {
+            arg == *flag || arg.starts_with(&format!("{}=", flag))
+        } else {
+            arg.starts_with(flag)
+        });
+        if denied {
+            return Some(arg.to_owned());
+        }
+        if arg.starts_with("-L") {
+            let (path, denied) = if arg == "-L" {
+                let path = args.peek().cloned().unwrap_or("");
+                (path, format!("{} {}", arg, path))
+            } else {
+                (&arg[2..], arg.to_owned())
+            };
+            // library paths can be prefixed by their kind, like `native=/usr/lib`
+            let path = path.splitn(2, '=').last().unwrap_or("");
+            if Path::new(path).is_absolute() {
+                return Some(denied);
+            }
+        }
+    }
+    None
+}
+
+
+
+/// Checks a toolchain is a channel name optionally followed by a `YYYY-MM-DD` date, or a
+/// `major.minor[.patch]` Rust version
+fn is_valid_toolchain(toolchain: &str) -> bool {
+    let version: Vec<&str> = toolchain.split('.').collect();
+    if (version.len() == 2 || version.len() == 3) &&
+       version.iter().all(|v| !v.is_empty() && v.chars().all(|c| c.is_ascii_digit())) {
+        return true;
+    }
+
+    let mut parts = toolchain.splitn(2, '-');
+    let channel = parts.next().unwrap_or("");
+    if channel != "stable" && channel != "beta" && channel != "nightly" {
+        return false;
+    }
+    match parts.next() {
+        None => true,
+        Some(date) => {
+            let date: Vec<&str> = date.split('-').collect();
+            date.len() == 3 &&
+            date.iter().zip(&[4, 2, 2]).all(|(part, &len)| {
+                part.len() == len && part.chars().all(|c| c.is_ascii_digit())
+            })
+        }
+    }
+}
+
+
+
+fn is_valid_url(url: &str) -> bool {
+    Url::parse(url).map(|u| u.scheme() == "http" || u.scheme() == "https").unwrap_or(false)
+}
+
+
+
+/// Deserializes `build-timeout` from an integer number of seconds or a duration string.
+fn deserialize_build_timeout<'de, D>(deserializer: D) -> StdResult<Option<Duration>, D::Error>
+    where D: Deserializer<'de>
+{
+    let timeout = match Value::deserialize(deserializer)? {
+        Value::Integer(secs) if secs > 0 => Duration::from_secs(secs as u64),
+        Value::Integer(secs) => {
+            return Err(D::Error::custom(format!("build-timeout must be positive, got {}", secs)));
+        }
+        Value::String(s) => parse_duration(&s).map_err(D::Error::custom)?,
+        _ => return Err(D::Error::custom("expected an integer or a duration string")),
+    };
+    Ok(Some(timeout))
+}
+
+
+
+/// Deserializes `max-output-size` from an integer number of bytes or a size string.
+fn deserialize_size<'de, D>(deserializer: D) -> StdResult<Option<u64>, D::Error>
+    where D: Deserializer<'de>
+{
+    let size = match Value::deserialize(deserializer)? {
+        Value::Integer(bytes) if bytes > 0 => bytes as u64,
+        Value::Integer(bytes) => {
+            return Err(D::Error::custom(format!("max-output-size must be positive, got {}",
+                                                bytes)));
+        }
+        Value::String(s) => parse_size(&s).map_err(D::Error::custom)?,
+        _ => return Err(D::Error::custom("expected an integer or a size string")),
+    };
+    Ok(Some(size))
+}
+
+
+/// Parses a size like `500MB` or `1 GB`, units are decimal.
+fn parse_size(s: &str) -> StdResult<u64, String> {
+    let invalid = || format!("invalid max-output-size '{}', expected a size like '500MB'", s);
+    let s = s.trim();
+    let number_len = s.find(|c: char| !c.is_digit(10)).unwrap_or_else(|| s.len());
+    let number: u64 = s[..number_len].parse().map_err(|_| invalid())?;
+    let multiplier = match s[number_len..].trim_start() {
+        "" | "B" => 1,
+        "KB" => 1000,
+        "MB" => 1000 * 1000,
+        "GB" => 1000 * 1000 * 1000,
+        _ => return Err(invalid()),
+    };
+    match number.checked_mul(multiplier) {
+        Some(0) => Err(format!("max-output-size must be positive, got '{}'", s)),
+        Some(size) if size <= i64::max_value() as u64 => Ok(size),
+        _ => Err(invalid()),
+    }
+}
+
+
+/// Converts `build-timeout` to seconds, clamped to `MAX_BUILD_TIMEOUT`.
+fn clamp_build_timeout(build_timeout: Option<Duration>) -> Option<u64> {
+    build_timeout.map(|timeout| cmp::min(timeout.as_secs(), MAX_BUILD_TIMEOUT))
+}
+
+
+/// Parses a duration like `15min`, `90s` or `1h 30min`.
+fn parse_duration(s: &str) -> StdResult<Duration, String> {
+    let invalid = || format!("invalid build-timeout '{}'", s);
+    let mut secs = 0u64;
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    while !rest.is_empty() {
+        let number_len = rest.find(|c: char| !c.is_digit(10)).unwrap_or_else(|| rest.len());
+        let number: u64 = rest[..number_len].parse().map_err(|_| invalid())?;
+        rest = rest[number_len..].trim_start();
+        let unit_len = rest.find(|c: char| !c.is_alphabetic()).unwrap_or_else(|| rest.len());
+        let multiplier = match &rest[..unit_len] {
+            "s" | "sec" | "secs" | "second" | "seconds" => 1,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60,
+            "h" | "hr" | "hrs" | "hour" | "hours" => 60 * 60,
+            _ => return Err(invalid()),
+        };
+        rest = rest[unit_len..].trim_start();
+        secs = number.checked_mul(multiplier)
+            .and_then(|n| secs.checked_add(n))
+            .filter(|secs| *secs <= i64::max_value() as u64)
+            .ok_or_else(invalid)?;
+    }
+    if secs == 0 {
+        return Err(format!("build-timeout must be positive, got '{}'", s));
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+
+
+/// Checks a path is relative, isn't empty and doesn't escape the crate root.
+///
+/// This is only a lexical check, `resolve_crate_file` checks the path on disk.
+fn is_path_inside_crate(path: &str) -> bool {
+    let path = Path::new(path);
+    path.components().any(|c| match c {
+        Component::Normal(_) => true,
+        _ => false,
+    }) && path.components().all(|c| match c {
+        Component::Normal(_) | Component::CurDir => true,
+        _ => false,
+    })
+}
+
+
+/// Resolves the path of a file inside the crate, given as `key` in the metadata.
+///
+/// Symbolic links are followed, the resolved path must be a file inside `crate_root`.
+fn resolve_crate_file(key: &str, path: &str, crate_root: &Path) -> Result<PathBuf> {
+    let outside = || format_err!("{} `{}` must be a path inside the crate", key, path);
+    if !is_path_inside_crate(path) {
+        return Err(outside());
+    }
+    let crate_root = crate_root.canonicalize()?;
+    let resolved = crate_root.join(path).canonicalize()
+        .map_err(|_| format_err!("{} `{}` does not exist", key, path))?;
+    if !resolved.starts_with(&crate_root) {
+        return Err(outside());
+    }
+    if !resolved.is_file() {
+        return Err(format_err!("{} `{}` is not a file", key, path));
+    }
+    Ok(resolved)
+}
+
+
+
+/// `rustdoc` arguments which take a path to a file
+const RUSTDOC_PATH_FLAGS: &'static [&'static str] = &[
+    "--html-in-header",
+    "--html-before-content",
+    "--html-after-content",
+    "--markdown-css",
+];
+
+
+/// Rewrites the paths given to `RUSTDOC_PATH_FLAGS` to absolute paths inside `crate_root`, both
+/// for `--flag path` and `--flag=path` forms.
+fn resolve_paths_in_args(args: &mut Vec<String>, crate_root: &Path) -> Result<()> {
+    fn resolve(flag: &str, path: &str, crate_root: &Path) -> Result<String> {
+        Ok(resolve_crate_file(flag, path, crate_root)?.to_string_lossy().into_owned())
+    }
+
+    let mut i = 0;
+    while i < args.len() {
+        let flag_value = args[i].find('=')
+            .map(|pos| (args[i][..pos].to_owned(), args[i][pos + 1..].to_owned()));
+        if RUSTDOC_PATH_FLAGS.contains(&args[i].as_str()) && i + 1 < args.len() {
+            args[i + 1] = resolve(&args[i], &args[i + 1], crate_root)?;
+            i += 1;
+        } else if let Some((flag, value)) = flag_value {
+            if RUSTDOC_PATH_FLAGS.contains(&flag.as_str()) {
+                args[i] = format!("{}={}", flag, resolve(&flag, &value, crate_root)?);
+            }
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+
+
+fn parse_manifest(manifest: &str) -> Result<Manifest> {
+    toml::from_str(manifest).map_err(|e| MetadataError::Parse(e).into())
+}
+
+
+
+fn read_file(path: &Path) -> Result<String> {
+    use std::fs::File;
+    let mut f = File::open(path)?;
+    let mut s = String::new();
+    f.read_to_string(&mut s)?;
+    Ok(s)
+}
+
+
+
+/// Returns the directory of a package manifest.
+///
+/// If the manifest path has no usable parent, like `Cargo.toml` or `/`, `fallback` is used if
+/// it has a manifest.
+fn package_source_dir(manifest_path: &Path, fallback: &Path) -> Result<PathBuf> {
+    match manifest_path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        Some(parent) => Ok(parent.to_path_buf()),
+        None if ["Cargo.toml", "Cargo.toml.orig"].iter().any(|c| fallback.join(c).is_file()) => {
+            Ok(fallback.to_path_buf())
+        }
+        None => Err(MetadataError::SourcePathUnavailable.into()),
+    }
+}
+
+
+
+/// Number of parent directories searched for the manifest of a package
+const MANIFEST_SEARCH_DEPTH: usize = 3;
+
+
+
+/// Walks up from a package source directory, at most `MANIFEST_SEARCH_DEPTH` levels, and
+/// returns the first directory with a manifest of the package named `name`.
+fn find_package_dir(src_path: &Path, name: &str) -> Option<PathBuf> {
+    for dir in src_path.ancestors().take(MANIFEST_SEARCH_DEPTH + 1) {
+        for c in ["Cargo.toml", "Cargo.toml.orig"].iter() {
+            let manifest_path = dir.join(c);
+            if !manifest_path.is_file() {
+                continue;
+            }
+            let manifest = match read_file(&manifest_path).and_then(|m| parse_manifest(&m)) {
+                Ok(manifest) => manifest,
+                Err(_) => continue,
+            };
+            if manifest.package.name.as_ref().map_or(false, |n| n == name) {
+                return Some(dir.to_path_buf());
+            }
+        }
+    }
+    None
+}
+
+
+
+/// Walks up from a package source directory, at most `MANIFEST_SEARCH_DEPTH` levels, and
+/// returns the first manifest which has a `[workspace]` table, if the package is a member of
+/// that workspace.
+fn find_workspace_manifest(src_path: &Path) -> Option<String> {
+    for dir in src_path.ancestors().take(MANIFEST_SEARCH_DEPTH + 1) {
+        let manifest_path = dir.join("Cargo.toml");
+        if !manifest_path.is_file() {
+            continue;
+        }
+        let manifest = match read_file(&manifest_path) {
+            Ok(manifest) => manifest,
+            Err(_) => continue,
+        };
+        match parse_manifest(&manifest) {
+            Ok(Manifest { workspace: Some(workspace), .. }) => {
+                if is_workspace_member(&workspace, dir, src_path) {
+                    return Some(manifest);
+                }
+                return None;
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to parse {}: {}", manifest_path.display(), e),
+        }
+    }
+    None
+}
+
+
+/// Checks the package in `package_dir` is a member of the workspace in `workspace_dir`.
+///
+/// Like cargo, the root package is always a member, other packages must match a `members`
+/// pattern and not be under an `exclude` path. Path dependencies are not followed.
+fn is_workspace_member(workspace: &ManifestWorkspace, workspace_dir: &Path,
+                       package_dir: &Path) -> bool {
+    let path = match package_dir.strip_prefix(workspace_dir) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    if path.components().next().is_none() {
+        return true;
+    }
+    if workspace.exclude.iter().any(|exclude| path.starts_with(exclude)) {
+        return false;
+    }
+    workspace.members.iter().any(|member| {
+        let pattern: Vec<_> = Path::new(member).components().collect();
+        let components: Vec<_> = path.components().collect();
+        pattern.len() == components.len() &&
+            pattern.iter().zip(components.iter()).all(|(p, c)| {
+                matches_wildcard(&p.as_os_str().to_string_lossy(),
+                                 &c.as_os_str().to_string_lossy())
+            })
+    })
+}
+
+
+/// Matches a path component against a pattern where `*` matches any characters.
+fn matches_wildcard(pattern: &str, s: &str) -> bool {
+    match pattern.find('*') {
+        None => pattern == s,
+        Some(pos) => {
+            let (prefix, rest) = (&pattern[..pos], &pattern[pos + 1..]);
+            s.starts_with(prefix) &&
+                (prefix.len()..s.len() + 1).any(|i| s.is_char_boundary(i) &&
+                                                    matches_wildcard(rest, &s[i..]))
+        }
+    }
+}
+
+
+
+fn deserialize_keys<'de, D>(deserializer: D) -> StdResult<Vec<String>, D::Error>
+    where D: Deserializer<'de>
+{
+    let table: BTreeMap<String, IgnoredAny> = Deserialize::deserialize(deserializer)?;
+    Ok(table.into_iter().map(|(k, _)| k).collect())
+}
+
+
+
+impl Metadata {
+    /// Reads metadata from the manifests of a package, see `from_source_dir`.
+    ///
+    /// If the package is a member of a workspace, `[workspace.metadata.docs.rs]` table of the
+    /// workspace manifest is used as a base, see `from_package_and_workspace`.
+    pub fn from_package(pkg: &Package) -> Result<Metadata> {
+        let src_path = package_source_dir(pkg.manifest_path(), &env::current_dir()?)?;
+        match find_package_dir(&src_path, pkg.name().as_str()) {
+            Some(package_dir) => Metadata::from_member_dir(&package_dir),
+            None => Metadata::from_member_dir(&src_path),
+        }
+    }
+
+
+    /// Reads metadata from a package source directory and the workspace it belongs to.
+    fn from_member_dir(src_path: &Path) -> Result<Metadata> {
+        let workspace_manifest = find_workspace_manifest(src_path);
+        Metadata::from_source_dir(src_path, workspace_manifest.as_ref().map(|m| m.as_str()))
+    }
+
+
+    /// Reads metadata of a workspace member.
+    ///
+    /// `[workspace.metadata.docs.rs]` table of the workspace manifest is used as a base, and every
+    /// key defined in `[package.metadata.docs.rs]` table of the package overrides it. Values are
+    /// replaced as a whole, arrays and tables are not merged.
+    pub fn from_package_and_workspace<P: AsRef<Path>>(pkg: &Package,
+                                                      workspace_manifest: P)
+                                                      -> Result<Metadata> {
+        let src_path = package_source_dir(pkg.manifest_path(), &env::current_dir()?)?;
+        Metadata::from_source_dir(&src_path, Some(&read_file(workspace_manifest.as_ref())?))
+    }
+
+
+    /// Reads metadata from the manifests in a package source directory.
+    ///
+    /// Packaged `Cargo.toml` and the original `Cargo.toml.orig` may not carry the same
+    /// `[package.metadata.docs.rs]` table, since packaging can drop keys the author wrote. If
+    /// both have the table, `Cargo.toml.orig` is merged on top of `Cargo.toml` with `merge`:
+    /// keys set in `Cargo.toml.orig` win, and keys only set in `Cargo.toml` are kept. A manifest
+    /// which fails to parse is skipped if the other one can be used.
+    ///
+    /// If a workspace manifest is given, the package tables are layered on top of its
+    /// `[workspace.metadata.docs.rs]` table.
+    ///
+    /// Metadata is only parsed, builder checks it with `validate` before building.
+    fn from_source_dir(src_path: &Path, workspace_manifest: Option<&str>) -> Result<Metadata> {
+        let mut manifest_found = false;
+        let mut manifest_parsed = false;
+        let mut parse_error = None;
+        let mut tables = Vec::new();
+        for c in ["Cargo.toml", "Cargo.toml.orig"].iter() {
+            let manifest_path = src_path.join(c);
+            if manifest_path.exists() {
+                manifest_found = true;
+                match parse_manifest(&read_file(&manifest_path)?) {
+                    Ok(manifest) => {
+                        manifest_parsed = true;
+                        tables.extend(manifest.package.metadata.docs.rs);
+                    }
+                    Err(e) => {
+                        warn!("Failed to parse {}: {}", manifest_path.display(), e);
+                        parse_error = Some(e);
+                    }
+                }
+            }
+        }
+        if !manifest_found {
+            return Err(MetadataError::ManifestNotFound.into());
+        }
+        if !manifest_parsed {
+            if let Some(e) = parse_error {
+                return Err(e);
+            }
+        }
+
+        let mut tables = tables.into_iter();
+        let mut table = tables.next();
+        if let Some(workspace_manifest) = workspace_manifest {
+            let workspace_table = parse_manifest(workspace_manifest)?.workspace
+                .and_then(|workspace| workspace.metadata.docs.rs);
+            table = match (workspace_table, table) {
+                (Some(mut workspace_table), Some(table)) => {
+                    workspace_table.extend(table);
+                    Some(workspace_table)
+                }
+                (workspace_table, table) => table.or(workspace_table),
+            };
+        }
+
+        let mut metadata = match table {
+            Some(table) => Metadata::from_table(table)?,
+            None => Metadata::default(),
+        };
+        for overlay in tables {
+            metadata = metadata.merge(Metadata::from_table(overlay)?);
+        }
+        Ok(metadata)
+    }
+
+    /// Reads metadata from a manifest file and checks it with `validate`, so contradictory
+    /// settings fail early.
+    ///
+    /// `from_str`, `from_bytes`, `from_reader` and `from_json` only parse the manifest.
+    pub fn from_manifest<P: AsRef<Path>>(path: P) -> Result<Metadata> {
+        use std::fs::File;
+        let metadata = Metadata::from_reader(File::open(path)?)?;
+        metadata.validate()?;
+        Ok(metadata)
+    }
+
+
+    /// Reads metadata from a manifest provided by a reader.
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Metadata> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Metadata::from_bytes(&bytes)
+    }
+
+
+    /// Reads metadata from a manifest in a byte buffer.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Metadata> {
+        let manifest = str::from_utf8(bytes)
+            .map_err(|e| format_err!("Manifest is not valid UTF-8: {}", e))?;
+        Metadata::from_str(manifest)
+    }
+
+
+    /// Reads metadata from the `metadata` object of a package in `cargo metadata` output.
+    ///
+    /// `null`, used by `cargo metadata` for packages without metadata, gives the default metadata.
+    pub fn from_json(value: &serde_json::Value) -> Result<Metadata> {
+        if value.is_null() {
+            return Ok(Metadata::default());
+        }
+        let manifest_metadata = ManifestMetadata::deserialize(value)
+            .map_err(|e| format_err!("Failed to parse package metadata: {}", e))?;
+        match manifest_metadata.docs.rs {
+            Some(table) => Metadata::from_table(table),
+            None => Ok(Metadata::default()),
+        }
+    }
+
+
+    fn from_str(manifest: &str) -> Result<Metadata> {
+        match parse_manifest(manifest)?.package.metadata.docs.rs {
+            Some(table) => Metadata::from_table(table),
+            None => Ok(Metadata::default()),
+        }
+    }
+
+
+    /// Creates metadata from a `[package.metadata.docs.rs]` table.
+    fn from_table(mut table: Table) -> Result<Metadata> {
+        let raw = table.clone();
+        let renamed_keys = canonicalize_keys(&mut table)?;
+        let skipped_entries = remove_non_string_entries(&mut table);
+        let mut metadata = Metadata::deserialize(Value::Table(table.clone()))
+            .map_err(MetadataError::Parse)?;
+
+        metadata.has_table = true;
+        metadata.raw = Some(raw);
+        metadata.renamed_keys = renamed_keys;
+        metadata.skipped_entries = skipped_entries;
+        metadata.build_timeout_seconds = clamp_build_timeout(metadata.build_timeout);
+        metadata.set_keys = table.keys()
+            .filter(|k| KNOWN_KEYS.contains(&k.as_str()))
+            .map(|k| if k == "rust-version" { "toolchain".to_owned() } else { k.clone() })
+            .collect();
+
+        metadata.unknown_keys.retain(|k| k != "rust-version");
+        if metadata.toolchain.is_none() {
+            if let Some(rust_version) = table.get("rust-version") {
+                let rust_version = rust_version.as_str()
+                    .ok_or_else(|| err_msg("rust-version must be a string"))?;
+                metadata.toolchain = Some(rust_version.to_owned());
+            }
+        }
+
+        if let Some(default_features) = metadata.default_features {
+            if !table.contains_key("no-default-features") {
+                metadata.no_default_features = !default_features;
+            }
+        }
+
+        if let Some(features) = table.get("features").and_then(|t| t.as_table()) {
+            for (target, target_features) in features {
+                let target_features: Vec<String> = target_features.clone().try_into()
+                    .map_err(MetadataError::Parse)?;
+                metadata.target_features.insert(target.clone(),
+                                                normalize_features(target_features));
+            }
+        }
+
+        metadata.string_keys = ["features", "rustc-args", "rustdoc-args", "dependencies"].iter()
+            .filter(|k| table.get(**k).map_or(false, |v| v.is_str()))
+            .map(|k| k.to_string())
+            .collect();
+
+        metadata.dependency_specs = match table.get("dependencies") {
+            Some(&Value::Table(ref dependencies)) => {
+                Some(dependencies.iter()
+                     .map(|(name, spec)| {
+                         (name.clone(), spec.as_str().unwrap_or(ANY_VERSION).to_owned())
+                     })
+                     .collect())
+            }
+            _ => metadata.dependencies.as_ref().map(|dependencies| {
+                dependencies.iter().map(|d| (d.clone(), ANY_VERSION.to_owned())).collect()
+            }),
+        };
+
+        if let Some(targets) = table.get("targets").and_then(|t| t.as_table()) {
+            for (target, overrides) in targets {
+                let overrides: TargetMetadata = overrides.clone().try_into()
+                    .map_err(MetadataError::Parse)?;
+                if is_cfg_expr(target) {
+                    CfgExpr::parse(target).map_err(err_msg)?;
+                    metadata.cfg_target_overrides.insert(target.clone(), overrides);
+                } else {
+                    metadata.target_overrides.insert(target.clone(), overrides);
+                }
+            }
+        }
+
+        for channel in CHANNELS {
+            if let Some(channel_table) = table.get(*channel) {
+                let channel_args: ChannelMetadata = channel_table.clone().try_into()
+                    .map_err(MetadataError::Parse)?;
+                metadata.channel_args.insert(channel.to_string(), channel_args);
+            }
+        }
+        metadata.unknown_keys.retain(|k| !CHANNELS.contains(&k.as_str()));
+
+        // unknown tables named after a target or a `cfg(...)` expression are target specific
+        // settings, like `targets` tables, other unknown tables are reported as unknown keys
+        let target_tables: Vec<String> = metadata.unknown_keys.iter()
+            .filter(|k| table.get(k.as_str()).map_or(false, |t| t.is_table()))
+            .filter(|k| is_cfg_expr(k) || is_target_table(k))
+            .cloned()
+            .collect();
+        for target in &target_tables {
+            let mut overrides: TargetMetadata = table[target.as_str()].clone().try_into()
+                .map_err(MetadataError::Parse)?;
+            if !is_cfg_expr(target) {
+                // rustdoc-args of a target table are appended to rustdoc-args
+                if let Some(rustdoc_args) = overrides.rustdoc_args.take() {
+                    metadata.per_target_rustdoc_args.insert(target.clone(), rustdoc_args);
+                }
+                if overrides == TargetMetadata::default() {
+                    continue;
+                }
+            }
+            let entry = if is_cfg_expr(target) {
+                CfgExpr::parse(target).map_err(err_msg)?;
+                metadata.cfg_target_overrides.entry(target.clone()).or_insert_with(Default::default)
+            } else {
+                metadata.target_overrides.entry(target.clone()).or_insert_with(Default::default)
+            };
+            // a `targets` table of the same target takes precedence
+            entry.features = entry.features.take().or(overrides.features);
+            entry.rustc_args = entry.rustc_args.take().or(overrides.rustc_args);
+            entry.rustdoc_args = entry.rustdoc_args.take().or(overrides.rustdoc_args);
+        }
+        metadata.unknown_keys.retain(|k| !target_tables.contains(k));
+        metadata.extra = metadata.unknown_keys.iter()
+            .map(|k| (k.clone(), table[k.as_str()].clone()))
+            .collect();
+
+        // default-target is the primary target and always comes first in targets
+        if let Some(ref default_target) = metadata.default_target {
+            if let Some(ref mut targets) = metadata.targets {
+                if targets.contains(default_target) {
+                    targets.retain(|t| t != default_target);
+                    targets.insert(0, default_target.clone());
+                }
+            }
+        }
+
+        Ok(metadata)
+    }
+
+
+    /// Parses metadata from a manifest and returns it with a list of warnings about the
+    /// `[package.metadata.docs.rs]` table.
+    pub fn from_str_with_warnings(manifest: &str) -> Result<(Metadata, Vec<MetadataWarning>)> {
+        let metadata = Metadata::from_str(manifest)?;
+        let warnings = metadata.warnings();
+        Ok((metadata, warnings))
+    }
+
+
+    /// Returns warnings about the `[package.metadata.docs.rs]` table to show in the build log.
+    pub fn warnings(&self) -> Vec<MetadataWarning> {
+        let mut warnings: Vec<MetadataWarning> = self.unknown_keys.iter()
+            .map(|k| MetadataWarning::UnknownKey {
+                key: k.clone(),
+                suggestion: suggest_key(k).map(|s| s.to_owned()),
+            })
+            .collect();
+        if let Some(ref rustdoc_map) = self.rustdoc_map {
+            for (name, url) in rustdoc_map {
+                if !is_valid_url(url) {
+                    warnings.push(MetadataWarning::Invalid {
+                        key: "rustdoc-map".to_owned(),
+                        reason: format!("rustdoc-map entry '{}' has an invalid URL '{}', \
+                                         ignoring it", name, url),
+                    });
+                }
+            }
+        }
+        for key in &self.string_keys {
+            warnings.push(MetadataWarning::Deprecated {
+                key: format!("{} = \"...\"", key),
+                replacement: format!("{} = [ \"...\" ]", key),
+            });
+        }
+        for &(ref key, ref canonical) in &self.renamed_keys {
+            warnings.push(MetadataWarning::Deprecated {
+                key: key.clone(),
+                replacement: canonical.clone(),
+            });
+        }
+        for &(ref key, ref entry) in &self.skipped_entries {
+            let list_key = key.rsplit('.').next().unwrap_or(key);
+            let reason = if ARGS_KEYS.contains(&list_key) {
+                format!("{} entry {} is not a string, ignoring {}", key, entry, key)
+            } else {
+                format!("{} entry {} is not a string, ignoring it", key, entry)
+            };
+            warnings.push(MetadataWarning::Invalid { key: key.clone(), reason: reason });
+        }
+        let targets = self.default_target.iter()
+            .map(|t| ("default-target", t))
+            .chain(self.targets.iter().flat_map(|t| t.iter()).map(|t| ("targets", t)))
+            .chain(self.exclude_targets.iter().flat_map(|t| t.iter())
+                   .map(|t| ("exclude-targets", t)));
+        for (key, target) in targets {
+            if target == ALL_TARGETS || KNOWN_TARGETS.contains(&target.as_str()) {
+                continue;
+            }
+            let reason = match suggest_target(target) {
+                Some(suggestion) => format!("unknown target '{}' in {}; did you mean '{}'?",
+                                            target, key, suggestion),
+                None => format!("unknown target '{}' in {}", target, key),
+            };
+            warnings.push(MetadataWarning::Invalid { key: key.to_owned(), reason: reason });
+        }
+        if let (&Some(ref default_target), &Some(ref targets)) = (&self.default_target,
+                                                                  &self.targets) {
+            if !self.has_all_targets() && !targets.contains(default_target) {
+                warnings.push(MetadataWarning::Invalid {
+                    key: "default-target".to_owned(),
+                    reason: format!("default-target '{}' is not one of the targets, it's built \
+                                     in addition to them", default_target),
+                });
+            }
+        }
+        if let Some(build_timeout) = self.build_timeout {
+            if build_timeout.as_secs() > MAX_BUILD_TIMEOUT {
+                warnings.push(MetadataWarning::Clamped {
+                    key: "build-timeout".to_owned(),
+                    reason: format!("build-timeout must be at most {}s, {}s is clamped to {}s",
+                                    MAX_BUILD_TIMEOUT, build_timeout.as_secs(),
+                                    MAX_BUILD_TIMEOUT),
+                });
+            }
+        }
+        if let Some(priority) = self.priority {
+            if priority != i64::from(self.build_priority()) {
+                warnings.push(MetadataWarning::Clamped {
+                    key: "priority".to_owned(),
+                    reason: format!("priority must be between {} and {}, {} is clamped to {}",
+                                    MIN_PRIORITY, MAX_PRIORITY, priority, self.build_priority()),
+                });
+            }
+        }
+        if self.all_features && self.no_default_features {
+            warnings.push(MetadataWarning::Redundant {
+                reason: "no-default-features is redundant when all-features is set".to_owned(),
+            });
+        }
+        for (key, problem) in self.legacy_problems() {
+            warnings.push(MetadataWarning::Invalid { key: key.to_owned(), reason: problem });
+        }
+        warnings
+    }
+
+
+    /// Returns `(unknown key, suggested key)` pairs for unknown keys which look like typos of
+    /// known keys.
+    pub fn key_suggestions(&self) -> Vec<(String, String)> {
+        self.unknown_keys.iter()
+            .filter_map(|k| suggest_key(k).map(|suggestion| (k.clone(), suggestion.to_owned())))
+            .collect()
+    }
+
+
+    /// Returns every key recognized in `[package.metadata.docs.rs]` table, see `KNOWN_KEYS`.
+    pub fn known_keys() -> &'static [&'static str] {
+        KNOWN_KEYS
+    }
+
+
+    /// Returns keys of a `[package.metadata.docs.rs]` table which are not recognized by docs.rs.
+    ///
+    /// Known keys in another spelling, like `all_features`, are recognized.
+    pub fn unknown_keys(table: &Table) -> Vec<String> {
+        table.keys()
+            .filter(|k| canonical_key(k).is_none())
+            .cloned()
+            .collect()
+    }
+
+
+    /// Returns metadata as a manifest with `[package.metadata.docs.rs]` table.
+    ///
+    /// Only the fields which are set are emitted, and parsing the returned manifest gives the
+    /// same metadata back. Numbers larger than a TOML integer are saturated to `i64::MAX`.
+    pub fn to_toml(&self) -> Value {
+        fn strings(v: &[String]) -> Value {
+            Value::Array(v.iter().map(|s| Value::String(s.clone())).collect())
+        }
+        fn integer(n: u64) -> Value {
+            Value::Integer(cmp::min(n, i64::max_value() as u64) as i64)
+        }
+        fn overrides_table(overrides: Option<&TargetMetadata>) -> Table {
+            let mut target_table = Table::new();
+            if let Some(overrides) = overrides {
+                if let Some(ref features) = overrides.features {
+                    target_table.insert("features".to_owned(), strings(features));
+                }
+                if let Some(ref rustc_args) = overrides.rustc_args {
+                    target_table.insert("rustc-args".to_owned(), strings(rustc_args));
+                }
+                if let Some(ref rustdoc_args) = overrides.rustdoc_args {
+                    target_table.insert("rustdoc-args".to_owned(), strings(rustdoc_args));
+                }
+            }
+            target_table
+        }
+
+        let mut table = Table::new();
+        if !self.target_features.is_empty() {
+            table.insert("features".to_owned(),
+                         Value::Table(self.target_features.iter()
+                             .map(|(k, v)| (k.clone(), strings(v)))
+                             .collect()));
+        } else if let Some(ref features) = self.features {
+            table.insert("features".to_owned(), strings(features));
+        }
+        if let Some(ref feature_matrix) = self.feature_matrix {
+            table.insert("feature-matrix".to_owned(),
+                         Value::Array(feature_matrix.iter().map(|f| strings(f)).collect()));
+        }
+        if self.all_features || self.is_set("all-features") {
+            table.insert("all-features".to_owned(), Value::Boolean(self.all_features));
+        }
+        if let Some(ref features_doc) = self.features_doc {
+            table.insert("features-doc".to_owned(), Value::String(features_doc.clone()));
+        }
+        if self.no_default_features || self.is_set("no-default-features") {
+            table.insert("no-default-features".to_owned(),
+                         Value::Boolean(self.no_default_features));
+        }
+        if let Some(default_features) = self.default_features {
+            table.insert("default-features".to_owned(), Value::Boolean(default_features));
+        }
+        if self.no_deps || self.is_set("no-deps") {
+            table.insert("no-deps".to_owned(), Value::Boolean(self.no_deps));
+        }
+        if self.document_private_items || self.is_set("document-private-items") {
+            table.insert("document-private-items".to_owned(),
+                         Value::Boolean(self.document_private_items));
+        }
+        if self.document_examples || self.is_set("document-examples") {
+            table.insert("document-examples".to_owned(), Value::Boolean(self.document_examples));
+        }
+        if let Some(ref exclude_deps) = self.exclude_deps {
+            table.insert("exclude-deps".to_owned(), strings(exclude_deps));
+        }
+        if let Some(ref default_target) = self.default_target {
+            table.insert("default-target".to_owned(), Value::String(default_target.clone()));
+        }
+        // overrides of listed targets are written in a `targets` table, other overrides in
+        // top-level target tables
+        let listed_targets: Vec<&String> = match self.targets {
+            Some(ref targets) if !self.has_all_targets() => targets.iter().collect(),
+            _ => Vec::new(),
+        };
+        if self.has_all_targets() {
+            table.insert("targets".to_owned(), Value::String(ALL_TARGETS.to_owned()));
+        } else if let Some(ref targets) = self.targets {
+            if !self.target_overrides.keys().any(|t| listed_targets.contains(&t)) {
+                table.insert("targets".to_owned(), strings(targets));
+            } else {
+                let targets_table = targets.iter()
+                    .map(|target| {
+                        (target.clone(),
+                         Value::Table(overrides_table(self.target_overrides.get(target))))
+                    })
+                    .collect();
+                table.insert("targets".to_owned(), Value::Table(targets_table));
+            }
+        }
+        let mut target_tables: BTreeMap<String, Table> = BTreeMap::new();
+        let overrides = self.target_overrides.iter()
+            .filter(|&(target, _)| !listed_targets.contains(&target))
+            .chain(self.cfg_target_overrides.iter());
+        for (target, overrides) in overrides {
+            target_tables.insert(target.clone(), overrides_table(Some(overrides)));
+        }
+        for (target, rustdoc_args) in &self.per_target_rustdoc_args {
+            target_tables.entry(target.clone())
+                .or_insert_with(Table::new)
+                .insert("rustdoc-args".to_owned(), strings(rustdoc_args));
+        }
+        if let Some(ref exclude_targets) = self.exclude_targets {
+            table.insert("exclude-targets".to_owned(), strings(exclude_targets));
+        }
+        if let Some(ref cargo_args) = self.cargo_args {
+            table.insert("cargo-args".to_owned(), strings(cargo_args));
+        }
+        if let Some(ref rustc_args) = self.rustc_args {
+            table.insert("rustc-args".to_owned(), strings(rustc_args));
+        }
+        if let Some(ref rustdoc_args) = self.rustdoc_args {
+            table.insert("rustdoc-args".to_owned(), strings(rustdoc_args));
+        }
+        if let Some(ref cfg) = self.cfg {
+            table.insert("cfg".to_owned(), strings(cfg));
+        }
+        if !self.docsrs_cfg || self.is_set("docsrs-cfg") {
+            table.insert("docsrs-cfg".to_owned(), Value::Boolean(self.docsrs_cfg));
+        }
+        for (target, target_table) in target_tables {
+            table.insert(target, Value::Table(target_table));
+        }
+        for (channel, channel_args) in &self.channel_args {
+            let mut channel_table = Table::new();
+            if let Some(ref rustc_args) = channel_args.rustc_args {
+                channel_table.insert("rustc-args".to_owned(), strings(rustc_args));
+            }
+            if let Some(ref rustdoc_args) = channel_args.rustdoc_args {
+                channel_table.insert("rustdoc-args".to_owned(), strings(rustdoc_args));
+            }
+            table.insert(channel.clone(), Value::Table(channel_table));
+        }
+        if let Some(ref rustdoc_header) = self.rustdoc_header {
+            table.insert("rustdoc-header".to_owned(), Value::String(rustdoc_header.clone()));
+        }
+        if let Some(ref readme) = self.readme {
+            table.insert("readme".to_owned(), Value::String(readme.clone()));
+        }
+        if let Some(ref extra_files) = self.extra_files {
+            table.insert("extra-files".to_owned(), strings(extra_files));
+        }
+        if let Some(ref documentation_url) = self.documentation_url {
+            table.insert("documentation".to_owned(), Value::String(documentation_url.clone()));
+        }
+        if let Some(ref rustdoc_map) = self.rustdoc_map {
+            table.insert("rustdoc-map".to_owned(),
+                         Value::Table(rustdoc_map.iter()
+                             .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+                             .collect()));
+        }
+        let pinned_specs = self.dependency_specs.as_ref()
+            .filter(|specs| specs.values().any(|spec| spec != ANY_VERSION));
+        if let Some(specs) = pinned_specs {
+            table.insert("dependencies".to_owned(),
+                         Value::Table(specs.iter()
+                             .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+                             .collect()));
+        } else if let Some(ref dependencies) = self.dependencies {
+            table.insert("dependencies".to_owned(), strings(dependencies));
+        }
+        if let Some(ref dependencies_map) = self.dependencies_map {
+            table.insert("dependencies-map".to_owned(),
+                         Value::Table(dependencies_map.iter()
+                             .map(|(k, v)| (k.clone(), strings(v)))
+                             .collect()));
+        }
+        if let Some(ref env) = self.env {
+            table.insert("env".to_owned(),
+                         Value::Table(env.iter()
+                             .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+                             .collect()));
+        }
+        if let Some(ref toolchain) = self.toolchain {
+            table.insert("toolchain".to_owned(), Value::String(toolchain.clone()));
+        }
+        if !self.build || self.is_set("build") {
+            table.insert("build".to_owned(), Value::Boolean(self.build));
+        }
+        if let Some(build_timeout) = self.build_timeout {
+            table.insert("build-timeout".to_owned(),
+                         integer(build_timeout.as_secs()));
+        }
+        if let Some(max_output_size) = self.max_output_size {
+            table.insert("max-output-size".to_owned(), integer(max_output_size));
+        }
+        if let Some(priority) = self.priority {
+            table.insert("priority".to_owned(), Value::Integer(priority));
+        }
+        if let Some(memory_limit_mb) = self.memory_limit_mb {
+            table.insert("memory-limit".to_owned(), integer(memory_limit_mb));
+        }
+        if let Some(cpu_quota) = self.cpu_quota {
+            table.insert("cpu-quota".to_owned(), Value::Integer(cpu_quota));
+        }
+
+        let mut docs = Table::new();
+        docs.insert("rs".to_owned(), Value::Table(table));
+        let mut metadata = Table::new();
+        metadata.insert("docs".to_owned(), Value::Table(docs));
+        let mut package = Table::new();
+        package.insert("metadata".to_owned(), Value::Table(metadata));
+        let mut manifest = Table::new();
+        manifest.insert("package".to_owned(), Value::Table(package));
+        Value::Table(manifest)
+    }
+
+
+    /// Returns a short summary of the settings for the build log, one setting per line.
+    ///
+    /// Settings which are not set or set to their default are left out, and arguments are only
+    /// counted.
+    pub fn summary(&self) -> String {
+        fn count(args: &Option<Vec<String>>) -> usize {
+            args.as_ref().map_or(0, |args| args.len())
+        }
+
+        let mut lines = Vec::new();
+        if self.has_all_targets() {
+            lines.push(format!("targets: {}", ALL_TARGETS));
+        } else if self.default_target.is_some() || self.targets.is_some() {
+            lines.push(format!("targets: {}", self.all_targets().join(", ")));
+        }
+        if let Some(ref features_doc) = self.features_doc {
+            lines.push(format!("features-doc: {}", features_doc));
+        }
+        match self.features {
+            Some(ref features) if features.is_empty() => lines.push("features: none".to_owned()),
+            Some(ref features) => lines.push(format!("features: {}", features.join(", "))),
+            None => {}
+        }
+        for (target, features) in &self.target_features {
+            lines.push(format!("features for {}: {}", target, features.join(", ")));
+        }
+        if self.all_features {
+            lines.push("all-features: true".to_owned());
+        }
+        if self.no_default_features {
+            lines.push("no-default-features: true".to_owned());
+        }
+        if self.no_deps {
+            lines.push("no-deps: true".to_owned());
+        }
+        if self.document_private_items {
+            lines.push("document-private-items: true".to_owned());
+        }
+        if self.document_examples {
+            lines.push("document-examples: true".to_owned());
+        }
+        if !self.docsrs_cfg {
+            lines.push("docsrs-cfg: false".to_owned());
+        }
+        for &(key, args) in [("cargo-args", &self.cargo_args),
+                             ("rustc-args", &self.rustc_args),
+                             ("rustdoc-args", &self.rustdoc_args),
+                             ("dependencies", &self.dependencies)].iter() {
+            if count(args) > 0 {
+                lines.push(format!("{}: {}", key, count(args)));
+            }
+        }
+        if let Some(ref toolchain) = self.toolchain {
+            lines.push(format!("toolchain: {}", toolchain));
+        }
+        if !self.build {
+            lines.push("build: false".to_owned());
+        }
+        if let Some(build_timeout) = self.build_timeout_seconds {
+            lines.push(format!("build-timeout: {}s", build_timeout));
+        }
+        if self.build_priority() != 0 {
+            lines.push(format!("priority: {}", self.build_priority()));
+        }
+        if let Some(memory_limit_mb) = self.memory_limit_mb {
+            lines.push(format!("memory-limit: {}MB", memory_limit_mb));
+        }
+        if let Some(cpu_quota) = self.cpu_quota {
+            lines.push(format!("cpu-quota: {}", cpu_quota));
+        }
+        lines.join("\n")
+    }
+
+
+    /// Returns a fingerprint of the metadata, to tell if a rebuild is needed.
+    ///
+    /// The hash is computed with 64-bit FNV-1a over `to_toml` output. Tables are sorted there,
+    /// so the hash is the same across runs and rustc versions, unlike `DefaultHasher`.
+    pub fn content_hash(&self) -> u64 {
+        fnv1a(self.to_toml().to_string().as_bytes())
+    }
+
+
+    /// Returns a key for caching build artifacts of this metadata, as 16 hex digits.
+    ///
+    /// Unlike `content_hash`, lists where the order has no effect on the build, like `features`
+    /// or `dependencies`, are sorted first, so equivalent metadata gets the same key. The order
+    /// of arguments like `rustc-args` matters and is kept.
+    pub fn cache_key(&self) -> String {
+        let mut value = self.to_toml();
+        if let Some(table) = value.as_table_mut()
+            .and_then(|t| t.get_mut("package")).and_then(|t| t.as_table_mut())
+            .and_then(|t| t.get_mut("metadata")).and_then(|t| t.as_table_mut())
+            .and_then(|t| t.get_mut("docs")).and_then(|t| t.as_table_mut())
+            .and_then(|t| t.get_mut("rs")).and_then(|t| t.as_table_mut()) {
+            for key in UNORDERED_KEYS {
+                if let Some(&mut Value::Array(ref mut values)) = table.get_mut(*key) {
+                    values.sort_by(|a, b| a.as_str().cmp(&b.as_str()));
+                    values.dedup();
+                }
+            }
+        }
+        format!("{:016x}", fnv1a(value.to_string().as_bytes()))
+    }
+
+
+    /// Returns settings which are different in `other`, in the order of their keys.
+    ///
+    /// Settings are compared as they're written by `to_toml`, so a setting which is only
+    /// spelled differently is not a change.
+    pub fn diff(&self, other: &Metadata) -> Vec<FieldChange> {
+        fn settings(metadata: &Metadata) -> Table {
+            metadata.to_toml()["package"]["metadata"]["docs"]["rs"].as_table()
+                .cloned()
+                .unwrap_or_default()
+        }
+
+        let old = settings(self);
+        let new = settings(other);
+        let fields: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+        fields.into_iter()
+            .filter(|field| old.get(*field) != new.get(*field))
+            .map(|field| FieldChange {
+                field: field.clone(),
+                old: old.get(field).map(|v| v.to_string()),
+                new: new.get(field).map(|v| v.to_string()),
+            })
+            .collect()
+    }
+
+
+    /// Returns every target documentation should be built for, see `effective_targets`.
+    ///
+    /// docs.rs host target is used if no target is set.
+    pub fn all_targets(&self) -> Vec<String> {
+        self.effective_targets(HOST_TARGET)
+    }
+
+
+    /// Returns every target documentation should be built for, in build order.
+    ///
+    /// This is the union of `default-target` and `targets` without duplicates, with the default
+    /// target first. If neither is set, `host_default` is used. `targets = "*"` is not
+    /// expanded, use `resolved_targets` for that.
+    pub fn effective_targets(&self, host_default: &str) -> Vec<String> {
+        let mut effective_targets: Vec<String> = Vec::new();
+        let targets = self.default_target.iter()
+            .chain(self.targets.iter().flat_map(|t| t.iter()))
+            .filter(|t| *t != ALL_TARGETS);
+        for target in targets {
+            if !effective_targets.contains(target) {
+                effective_targets.push(target.clone());
+            }
+        }
+        if effective_targets.is_empty() {
+            effective_targets.push(host_default.to_owned());
+        }
+        effective_targets
+    }
+
+
+    /// Returns metadata of each documentation build docs.rs runs.
+    ///
+    /// Every feature set of `feature-matrix` is built separately with only its features, and
+    /// `all-features` doesn't apply to them. If `feature-matrix` is not set, this is a single
+    /// build with `features`, `all-features` and `no-default-features`.
+    pub fn matrix_jobs(&self) -> Vec<Metadata> {
+        let mut base = self.clone();
+        base.feature_matrix = None;
+        match self.feature_matrix {
+            Some(ref feature_matrix) => feature_matrix.iter().map(|features| {
+                let mut job = base.clone();
+                job.features = Some(features.clone());
+                job.all_features = false;
+                job
+            }).collect(),
+            None => vec![base],
+        }
+    }
+
+
+    /// Checks the size of the generated documentation against `max-output-size`.
+    pub fn check_output_size(&self, size: u64) -> Result<()> {
+        match self.max_output_size {
+            Some(max_output_size) if size > max_output_size => {
+                Err(format_err!("documentation is {} bytes, which is larger than \
+                                 max-output-size of {} bytes", size, max_output_size))
+            }
+            _ => Ok(()),
+        }
+    }
+
+
+    /// Returns priority of the crate in the build queue, 0 if it's not set.
+    ///
+    /// Priority is clamped between `MIN_PRIORITY` and `MAX_PRIORITY`.
+    pub fn build_priority(&self) -> i32 {
+        let priority = self.priority.unwrap_or(0);
+        cmp::max(i64::from(MIN_PRIORITY), cmp::min(priority, i64::from(MAX_PRIORITY))) as i32
+    }
+
+
+    /// Returns dependencies which shouldn't be documented, empty if `no-deps` is set.
+    pub fn excluded_deps(&self) -> Vec<String> {
+        match self.exclude_deps {
+            Some(ref exclude_deps) if !self.no_deps => exclude_deps.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+
+    /// Returns `true` if `default-target` is set in the manifest, rather than docs.rs falling
+    /// back to its own default target.
+    pub fn has_explicit_default_target(&self) -> bool {
+        self.is_set("default-target")
+    }
+
+
+    /// Returns `true` if `key` is set in the manifest, even if it's set to its default value.
+    pub fn is_set(&self, key: &str) -> bool {
+        self.set_keys.contains(key)
+    }
+
+
+    /// Returns `true` if the manifest has a `[package.metadata.docs.rs]` table.
+    ///
+    /// This tells apart a crate without docs.rs configuration from a crate which is configured
+    /// with the default settings, like an empty table.
+    pub fn has_metadata_table(&self) -> bool {
+        self.has_table
+    }
+
+
+    /// Layers `overlay` on top of this metadata.
+    ///
+    /// Fields set in `overlay` replace the fields of this metadata, lists are not concatenated.
+    /// Optional fields are set if they are `Some`, and boolean fields are set if their key is
+    /// set in the manifest of `overlay`, see `is_set`. Target overrides and per-target
+    /// `rustdoc-args` are merged for each target.
+    pub fn merge(self, overlay: Metadata) -> Metadata {
+        let mut merged = self;
+
+        if overlay.is_set("all-features") {
+            merged.all_features = overlay.all_features;
+        }
+        if overlay.is_set("no-default-features") || overlay.is_set("default-features") {
+            merged.no_default_features = overlay.no_default_features;
+        }
+        if overlay.is_set("no-deps") {
+            merged.no_deps = overlay.no_deps;
+        }
+        if overlay.is_set("document-private-items") {
+            merged.document_private_items = overlay.document_private_items;
+        }
+        if overlay.is_set("document-examples") {
+            merged.document_examples = overlay.document_examples;
+        }
+        if overlay.is_set("build") {
+            merged.build = overlay.build;
+        }
+        if overlay.is_set("docsrs-cfg") {
+            merged.docsrs_cfg = overlay.docsrs_cfg;
+        }
+
+        if overlay.features.is_some() || !overlay.target_features.is_empty() {
+            merged.features = overlay.features;
+            merged.target_features = overlay.target_features;
+        }
+        if overlay.targets.is_some() {
+            merged.targets = overlay.targets;
+        }
+        if overlay.dependencies.is_some() {
+            merged.dependencies = overlay.dependencies;
+            merged.dependency_specs = overlay.dependency_specs;
+        }
+
+        macro_rules! replace {
+            ($($field:ident),*) => {
+                $(
+                    if overlay.$field.is_some() {
+                        merged.$field = overlay.$field;
+                    }
+                )*
+            }
+        }
+        replace!(feature_matrix, features_doc, default_features, exclude_deps, default_target,
+                 exclude_targets, cargo_args, rustc_args, rustdoc_args, cfg, rustdoc_header,
+                 readme, extra_files, rustdoc_map, documentation_url, dependencies_map, env,
+                 toolchain, build_timeout, max_output_size, priority, memory_limit_mb,
+                 cpu_quota);
+        merged.build_timeout_seconds = clamp_build_timeout(merged.build_timeout);
+
+        merged.target_overrides.extend(overlay.target_overrides);
+        merged.cfg_target_overrides.extend(overlay.cfg_target_overrides);
+        merged.per_target_rustdoc_args.extend(overlay.per_target_rustdoc_args);
+        merged.channel_args.extend(overlay.channel_args);
+        for key in overlay.unknown_keys {
+            if !merged.unknown_keys.contains(&key) {
+                merged.unknown_keys.push(key);
+            }
+        }
+        for key in overlay.string_keys {
+            if !merged.string_keys.contains(&key) {
+                merged.string_keys.push(key);
+            }
+        }
+        for key in overlay.renamed_keys {
+            if !merged.renamed_keys.contains(&key) {
+                merged.renamed_keys.push(key);
+            }
+        }
+        for entry in overlay.skipped_entries {
+            if !merged.skipped_entries.contains(&entry) {
+                merged.skipped_entries.push(entry);
+            }
+        }
+        merged.set_keys.extend(overlay.set_keys);
+        if let Some(overlay_raw) = overlay.raw {
+            merged.raw.get_or_insert_with(Table::new).extend(overlay_raw);
+        }
+        merged.extra.extend(overlay.extra);
+        merged.has_table = merged.has_table || overlay.has_table;
+        merged
+    }
+
+
+    /// Returns system packages to install with a package manager, like `apt`.
+    ///
+    /// Falls back to `dependencies` if `dependencies-map` has no entry for the package manager.
+    pub fn system_packages_for(&self, pkg_mgr: &str) -> Vec<String> {
+        self.dependencies_map.as_ref()
+            .and_then(|map| map.get(pkg_mgr))
+            .or(self.dependencies.as_ref())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+
+    /// Returns `true` if `targets = "*"` is used.
+    pub fn has_all_targets(&self) -> bool {
+        self.targets.as_ref().map_or(false, |t| t.len() == 1 && t[0] == ALL_TARGETS)
+    }
+
+
+    /// Returns every target documentation should be built for with `targets = "*"` expanded
+    /// to `supported` targets, except `exclude-targets`.
+    pub fn resolved_targets(&self, supported: &[String]) -> Vec<String> {
+        if !self.has_all_targets() {
+            return self.all_targets();
+        }
+        let excluded = self.exclude_targets.as_ref();
+        let supported = supported.iter()
+            .filter(|t| !excluded.map_or(false, |excluded| excluded.contains(t)));
+        let mut resolved_targets: Vec<String> = Vec::new();
+        for target in self.default_target.iter().chain(supported) {
+            if !resolved_targets.contains(target) {
+                resolved_targets.push(target.clone());
+            }
+        }
+        resolved_targets
+    }
+
+
+    /// Returns warnings about `exclude-targets` entries which are not in `supported` targets.
+    pub fn exclude_targets_warnings(&self, supported: &[String]) -> Vec<MetadataWarning> {
+        self.exclude_targets.iter()
+            .flat_map(|t| t.iter())
+            .filter(|t| !supported.contains(t))
+            .map(|t| MetadataWarning::Invalid {
+                key: "exclude-targets".to_owned(),
+                reason: format!("exclude-targets entry '{}' is not a supported target, \
+                                 ignoring it", t),
+            })
+            .collect()
+    }
+
+
+    /// Returns metadata for a target with its overrides applied.
+    ///
+    /// Overridden fields replace the fields of the base metadata. Overrides of `cfg(...)`
+    /// expressions matching the target are applied first, so overrides of the target itself
+    /// take precedence over them.
+    pub fn resolve(&self, target: &str) -> Metadata {
+        let cfg_overrides = self.cfg_target_overrides.iter()
+            .filter(|&(cfg, _)| CfgExpr::parse(cfg).map(|e| e.matches(target)).unwrap_or(false))
+            .map(|(_, overrides)| overrides);
+        let mut metadata = self.clone();
+        for overrides in cfg_overrides.chain(self.target_overrides.get(target)) {
+            if overrides.features.is_some() {
+                metadata.features = overrides.features.clone();
+            }
+            if overrides.rustc_args.is_some() {
+                metadata.rustc_args = overrides.rustc_args.clone();
+            }
+            if overrides.rustdoc_args.is_some() {
+                metadata.rustdoc_args = overrides.rustdoc_args.clone();
+            }
+        }
+        metadata
+    }
+
+
+    /// Returns features docs.rs will build for a target.
+    ///
+    /// Features defined for the target in `[package.metadata.docs.rs.features]` table are used
+    /// first, otherwise this falls back to the target overrides and the `features` array.
+    pub fn features_for(&self, target: &str) -> Option<Vec<String>> {
+        match self.target_features.get(target) {
+            Some(features) => Some(features.clone()),
+            None => self.resolve(target).features,
+        }
+    }
+
+
+    /// Returns `rustc` and `rustdoc` arguments for a release channel.
+    ///
+    /// Global arguments come first and the arguments of the channel are appended to them.
+    /// `channel` can also be a toolchain, like `nightly-2019-02-01`.
+    pub fn args_for_channel(&self, channel: &str) -> (Vec<String>, Vec<String>) {
+        let mut rustc_args = self.rustc_args.clone().unwrap_or_default();
+        let mut rustdoc_args = self.rustdoc_args.clone().unwrap_or_default();
+        let channel = channel.splitn(2, '-').next().unwrap_or("");
+        if let Some(channel_args) = self.channel_args.get(channel) {
+            if let Some(ref args) = channel_args.rustc_args {
+                rustc_args.extend(args.iter().cloned());
+            }
+            if let Some(ref args) = channel_args.rustdoc_args {
+                rustdoc_args.extend(args.iter().cloned());
+            }
+        }
+        (rustc_args, rustdoc_args)
+    }
+
+
+    /// Returns `rustdoc` arguments for a target.
+    ///
+    /// Global `rustdoc-args` come first, followed by the target specific arguments.
+    pub fn rustdoc_args_for(&self, target: &str) -> Vec<String> {
+        let mut rustdoc_args = self.resolve(target).rustdoc_args.unwrap_or_default();
+        if let Some(target_rustdoc_args) = self.per_target_rustdoc_args.get(target) {
+            rustdoc_args.extend(target_rustdoc_args.iter().cloned());
+        }
+        rustdoc_args
+    }
+
+
+    /// Returns metadata docs.rs builds a target with on a release channel.
+    ///
+    /// Target overrides are applied like in `resolve`. `rustc-args` and `rustdoc-args` of the
+    /// result are the effective arguments: global or overridden arguments first, followed by
+    /// the per-target `rustdoc-args` and the arguments of the channel.
+    pub fn resolve_build(&self, target: &str, channel: &str) -> Metadata {
+        let mut metadata = self.resolve(target);
+        metadata.rustdoc_args = Some(self.rustdoc_args_for(target));
+        let (rustc_args, rustdoc_args) = metadata.args_for_channel(channel);
+        metadata.rustc_args = if rustc_args.is_empty() { None } else { Some(rustc_args) };
+        metadata.rustdoc_args = if rustdoc_args.is_empty() { None } else { Some(rustdoc_args) };
+        metadata
+    }
+
+
+    /// Returns the path of `rustdoc-header` resolved against the crate root.
+    ///
+    /// Paths escaping the crate root, also through symbolic links, or which are not existing
+    /// files are rejected.
+    pub fn rustdoc_header_path(&self, crate_root: &Path) -> Result<Option<PathBuf>> {
+        match self.rustdoc_header {
+            Some(ref rustdoc_header) => {
+                resolve_crate_file("rustdoc-header", rustdoc_header, crate_root).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+
+    /// Rewrites paths of `--html-in-header`, `--html-before-content`, `--html-after-content` and
+    /// `--markdown-css` in `rustdoc-args` to absolute paths against the crate root, so they
+    /// don't depend on the working directory of the build.
+    ///
+    /// Target and channel specific `rustdoc-args` are rewritten too. Paths escaping the crate
+    /// root, also through symbolic links, or which are not existing files are rejected.
+    pub fn resolve_rustdoc_paths(&mut self, crate_root: &Path) -> Result<()> {
+        if let Some(ref mut rustdoc_args) = self.rustdoc_args {
+            resolve_paths_in_args(rustdoc_args, crate_root)?;
+        }
+        for rustdoc_args in self.per_target_rustdoc_args.values_mut() {
+            resolve_paths_in_args(rustdoc_args, crate_root)?;
+        }
+        let overrides = self.target_overrides.values_mut()
+            .chain(self.cfg_target_overrides.values_mut());
+        for rustdoc_args in overrides.filter_map(|o| o.rustdoc_args.as_mut()) {
+            resolve_paths_in_args(rustdoc_args, crate_root)?;
+        }
+        for rustdoc_args in self.channel_args.values_mut().filter_map(|c| c.rustdoc_args.as_mut()) {
+            resolve_paths_in_args(rustdoc_args, crate_root)?;
+        }
+        Ok(())
+    }
+
+
+    /// Returns the paths of `extra-files` resolved against the crate root.
+    ///
+    /// Paths escaping the crate root, also through symbolic links, or which are not existing
+    /// files are rejected.
+    pub fn extra_files_paths(&self, crate_root: &Path) -> Result<Vec<PathBuf>> {
+        self.extra_files.iter()
+            .flat_map(|f| f.iter())
+            .map(|extra_file| resolve_crate_file("extra-files", extra_file, crate_root))
+            .collect()
+    }
+
+
+    /// Returns `--extern-html-root-url` arguments for `rustdoc-map` entries.
+    pub fn rustdoc_map_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(ref rustdoc_map) = self.rustdoc_map {
+            for (name, url) in rustdoc_map.iter().filter(|&(_, url)| is_valid_url(url)) {
+                args.push("--extern-html-root-url".to_owned());
+                args.push(format!("{}={}", name.replace("-", "_"), url));
+            }
+        }
+        args
+    }
+
+
+    /// Returns whether default features are disabled and the features to build.
+    ///
+    /// `features-doc` takes precedence over every other feature setting.
+    pub fn effective_features(&self) -> (bool, Vec<String>) {
+        match self.features_doc {
+            Some(ref features_doc) => (true, vec![features_doc.clone()]),
+            None => (self.no_default_features, self.features.clone().unwrap_or_default()),
+        }
+    }
+
+
+    /// Returns entries of `features` which are not one of `declared_features` of the crate.
+    ///
+    /// Only plain features are checked, `dep:name`, `name/feature` and `name?/feature` refer to
+    /// dependencies and are never reported.
+    pub fn unknown_features(&self, declared_features: &[String]) -> Vec<String> {
+        self.features.iter()
+            .flat_map(|features| features.iter())
+            .filter(|feature| match classify_feature(feature) {
+                FeatureRef::Feature(ref name) => !declared_features.contains(name),
+                _ => false,
+            })
+            .cloned()
+            .collect()
+    }
+
+
+    /// Returns `features` with glob patterns like `backend-*` expanded to the matching
+    /// `all_crate_features`.
+    ///
+    /// Entries without a `*` are kept unchanged, and a feature is never listed twice.
+    pub fn expand_features(&self, all_crate_features: &[String]) -> Vec<String> {
+        let mut expanded: Vec<String> = Vec::new();
+        for feature in self.features.iter().flat_map(|features| features.iter()) {
+            let matches: Vec<&String> = if feature.contains('*') {
+                all_crate_features.iter().filter(|f| matches_glob(feature, f)).collect()
+            } else {
+                vec![feature]
+            };
+            for feature in matches {
+                if !expanded.contains(feature) {
+                    expanded.push(feature.clone());
+                }
+            }
+        }
+        expanded
+    }
+
+
+    /// Returns warnings about glob patterns in `features` which match none of
+    /// `all_crate_features`.
+    pub fn features_warnings(&self, all_crate_features: &[String]) -> Vec<MetadataWarning> {
+        self.features.iter()
+            .flat_map(|features| features.iter())
+            .filter(|f| f.contains('*') && !all_crate_features.iter().any(|c| matches_glob(f, c)))
+            .map(|f| MetadataWarning::Invalid {
+                key: "features".to_owned(),
+                reason: format!("features pattern '{}' doesn't match any feature, ignoring it",
+                                f),
+            })
+            .collect()
+    }
+
+
+    /// Returns `true` if documentation is built with `--cfg docsrs`, which is the default.
+    pub fn wants_docsrs_cfg(&self) -> bool {
+        self.docsrs_cfg
+    }
+
+
+    /// Returns `true` if every feature is built or `cfg` values are set, which usually means
+    /// the crate documents items behind features or cfgs with `#[doc(cfg(...))]`.
+    pub fn all_features_or_cfg(&self) -> bool {
+        self.uses_all_features() || self.cfg.as_ref().map_or(false, |cfg| !cfg.is_empty())
+    }
+
+
+    /// Returns `true` if every feature should be built, `features-doc` disables `all-features`.
+    pub fn uses_all_features(&self) -> bool {
+        self.all_features && self.features_doc.is_none()
+    }
+
+
+    /// Returns arguments the builder should pass to `cargo doc`.
+    ///
+    /// Feature flags come first, followed by `default_rustdoc_args`.
+    pub fn rustdoc_flags(&self) -> Vec<String> {
+        let mut flags = self.feature_flags();
+        flags.extend(self.default_rustdoc_args());
+        flags
+    }
+
+
+    /// Returns feature flags for `cargo doc`.
+    ///
+    /// Default features are built unless `no-default-features` is set, and listed features are
+    /// built in addition to them:
+    ///
+    /// * nothing set: no flags, only default features are built
+    /// * `features`: `--features` for each feature, in addition to default features
+    /// * `features` and `no-default-features`: `--no-default-features` and `--features` for each
+    ///   feature, only listed features are built
+    /// * `features = []` and `no-default-features`: only `--no-default-features`, no feature is
+    ///   built
+    /// * `all-features`: `--all-features`
+    pub fn feature_flags(&self) -> Vec<String> {
+        let mut flags = Vec::new();
+        if self.uses_all_features() {
+            flags.push("--all-features".to_owned());
+        }
+        let (no_default_features, features) = self.effective_features();
+        if no_default_features {
+            flags.push("--no-default-features".to_owned());
+        }
+        for feature in features {
+            flags.push("--features".to_owned());
+            flags.push(feature);
+        }
+        flags
+    }
+
+
+    /// Returns arguments of the `cargo doc` invocation docs.rs runs for a target.
+    ///
+    /// `cargo-args` come first, followed by feature flags for the target, see `feature_flags`,
+    /// `--no-deps` and `--examples` if they're set and `--target`.
+    pub fn to_cargo_args(&self, target: &str) -> Vec<String> {
+        let mut target_metadata = self.clone();
+        target_metadata.features = self.features_for(target);
+        let mut args = self.cargo_args.clone().unwrap_or_default();
+        args.extend(target_metadata.feature_flags());
+        if self.no_deps {
+            args.push("--no-deps".to_owned());
+        }
+        if self.document_examples {
+            args.push("--examples".to_owned());
+        }
+        args.push("--target".to_owned());
+        args.push(target.to_owned());
+        args
+    }
+
+
+    /// Returns `--cfg docsrs` unless `docsrs-cfg` is false, `--cfg` arguments for `cfg` values,
+    /// `--document-private-items` if `document-private-items` is set and `rustdoc-args`, in this
+    /// order.
+    ///
+    /// These are the arguments docs.rs passes to `rustdoc`, which can be used to reproduce a
+    /// docs.rs build locally.
+    pub fn default_rustdoc_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.wants_docsrs_cfg() {
+            args.push("--cfg".to_owned());
+            args.push("docsrs".to_owned());
+        }
+        if let Some(ref cfg) = self.cfg {
+            for value in cfg {
+                args.push("--cfg".to_owned());
+                args.push(value.clone());
+            }
+        }
+        if self.document_private_items {
+            args.push("--document-private-items".to_owned());
+        }
+        if let Some(ref rustdoc_args) = self.rustdoc_args {
+            args.extend(rustdoc_args.iter().cloned());
+        }
+        args
+    }
+
+
+    /// Checks metadata for contradictory settings and for lists which are too long.
+    ///
+    /// The builder uses `validate_build` instead, which doesn't reject crates which built
+    /// before docs.rs validated metadata.
+    pub fn validate(&self) -> Result<()> {
+        if let Some((_, problem)) = self.legacy_problems().into_iter().next() {
+            return Err(err_msg(problem));
+        }
+        self.validate_build()
+    }
+
+
+    /// Checks metadata like `validate`, except the problems of keys docs.rs accepted before it
+    /// validated metadata, see `LEGACY_KEYS`, like `all-features` combined with a `features`
+    /// list. These are reported by `warnings`, so crates which built before keep building.
+    pub fn validate_build(&self) -> Result<()> {
+        if self.default_features == Some(self.no_default_features) {
+            return Err(err_msg("default-features and no-default-features contradict each other"));
+        }
+        for &(key, targets) in [("targets", &self.targets),
+                                ("exclude-targets", &self.exclude_targets)].iter() {
+            let mut seen = BTreeSet::new();
+            for target in targets.iter().flat_map(|t| t.iter()) {
+                if !seen.insert(target) {
+                    return Err(format_err!("{} has target `{}` more than once", key, target));
+                }
+            }
+        }
+        if let (Some(targets), Some(exclude_targets)) = (self.targets.as_ref(),
+                                                         self.exclude_targets.as_ref()) {
+            if let Some(target) = targets.iter().find(|t| exclude_targets.contains(t)) {
+                return Err(format_err!("target `{}` is in both targets and exclude-targets",
+                                       target));
+            }
+        }
+        if let (Some(default_target), Some(exclude_targets)) = (self.default_target.as_ref(),
+                                                                self.exclude_targets.as_ref()) {
+            if exclude_targets.contains(default_target) {
+                return Err(format_err!("default-target `{}` can't be in exclude-targets",
+                                       default_target));
+            }
+        }
+        if self.build_timeout == Some(Duration::from_secs(0)) {
+            return Err(err_msg("build-timeout must be positive"));
+        }
+        if let Some(memory_limit_mb) = self.memory_limit_mb {
+            if memory_limit_mb == 0 || memory_limit_mb > MAX_MEMORY_LIMIT {
+                return Err(format_err!("memory-limit must be between 1 and {} megabytes, got {}",
+                                       MAX_MEMORY_LIMIT, memory_limit_mb));
+            }
+        }
+        if let Some(cpu_quota) = self.cpu_quota {
+            if cpu_quota <= 0 || cpu_quota > i64::from(MAX_CPU_QUOTA) {
+                return Err(format_err!("cpu-quota must be between 1 and {}, got {}",
+                                       MAX_CPU_QUOTA, cpu_quota));
+            }
+        }
+        if let Some(ref readme) = self.readme {
+            if !is_path_inside_crate(readme) {
+                return Err(format_err!("readme `{}` must be a path inside the crate", readme));
+            }
+        }
+        for extra_file in self.extra_files.iter().flat_map(|f| f.iter()) {
+            if !is_path_inside_crate(extra_file) {
+                return Err(format_err!("extra-files `{}` must be a path inside the crate",
+                                       extra_file));
+            }
+        }
+        if let Some(ref documentation_url) = self.documentation_url {
+            if !is_valid_url(documentation_url) {
+                return Err(format_err!("documentation `{}` is not a valid http(s) URL",
+                                       documentation_url));
+            }
+        }
+        if let Some(ref toolchain) = self.toolchain {
+            if !is_valid_toolchain(toolchain) {
+                return Err(format_err!("Invalid toolchain: `{}`, expected `stable`, `beta` or \
+                                        `nightly` optionally followed by a date, or a Rust \
+                                        version", toolchain));
+            }
+        }
+        let limit_problem = self.limit_problems(MAX_LIST_LENGTH, MAX_LIST_BYTES).into_iter()
+            .find(|&(key, _)| !LEGACY_KEYS.contains(&key));
+        match limit_problem {
+            Some((_, problem)) => Err(err_msg(problem)),
+            None => Ok(()),
+        }
+    }
+
+
+    /// Returns `(key, problem)` pairs for problems of keys in `LEGACY_KEYS`, which `validate`
+    /// rejects and `validate_build` only warns about.
+    fn legacy_problems(&self) -> Vec<(&'static str, String)> {
+        let mut problems = Vec::new();
+        if self.all_features && self.features.as_ref().map_or(false, |f| !f.is_empty()) {
+            problems.push(("features", "all-features can't be combined with an explicit features \
+                                        list, features are ignored when all-features is set"
+                                       .to_owned()));
+        }
+        problems.extend(self.limit_problems(MAX_LIST_LENGTH, MAX_LIST_BYTES).into_iter()
+                        .filter(|&(key, _)| LEGACY_KEYS.contains(&key)));
+        problems
+    }
+
+
+    /// Checks lists like `features` and `rustc-args` have at most `max_length` entries, which
+    /// are at most `max_bytes` long in total.
+    ///
+    /// `validate` checks this with `MAX_LIST_LENGTH` and `MAX_LIST_BYTES`.
+    pub fn validate_limits(&self, max_length: usize, max_bytes: usize) -> Result<()> {
+        match self.limit_problems(max_length, max_bytes).into_iter().next() {
+            Some((_, problem)) => Err(err_msg(problem)),
+            None => Ok(()),
+        }
+    }
+
+
+    /// Returns `(key, problem)` pairs for lists which are too long, see `validate_limits`.
+    fn limit_problems(&self, max_length: usize, max_bytes: usize)
+                      -> Vec<(&'static str, String)> {
+        let mut problems = Vec::new();
+        let lists = [("features", &self.features),
+                     ("exclude-deps", &self.exclude_deps),
+                     ("targets", &self.targets),
+                     ("exclude-targets", &self.exclude_targets),
+                     ("cargo-args", &self.cargo_args),
+                     ("rustc-args", &self.rustc_args),
+                     ("rustdoc-args", &self.rustdoc_args),
+                     ("cfg", &self.cfg),
+                     ("extra-files", &self.extra_files),
+                     ("dependencies", &self.dependencies)];
+        for &(key, list) in lists.iter() {
+            let list = match *list {
+                Some(ref list) => list,
+                None => continue,
+            };
+            if list.len() > max_length {
+                problems.push((key, format!("{} has {} entries, at most {} are allowed",
+                                            key, list.len(), max_length)));
+                continue;
+            }
+            let bytes: usize = list.iter().map(|entry| entry.len()).sum();
+            if bytes > max_bytes {
+                problems.push((key, format!("{} is {} bytes long, at most {} bytes are allowed",
+                                            key, bytes, max_bytes)));
+            }
+        }
+        problems
+    }
+
+
+    /// Checks `cargo-args`, `rustc-args` and `rustdoc-args` don't have arguments which can
+    /// interfere with the build environment, like `--out-dir` or `-L /etc`, and `env` doesn't
+    /// set variables like `RUSTFLAGS` or `LD_PRELOAD`.
+    ///
+    /// Target and channel specific arguments and `env` values are checked too.
+    pub fn sanitize_args(&self) -> Result<()> {
+        let mut args = Vec::new();
+        for &(key, list) in [("cargo-args", &self.cargo_args),
+                             ("rustc-args", &self.rustc_args),
+                             ("rustdoc-args", &self.rustdoc_args)].iter() {
+            if let Some(ref list) = *list {
+                args.push((key.to_owned(), list.clone()));
+            }
+        }
+        for (target, overrides) in self.target_overrides.iter()
+                                       .chain(self.cfg_target_overrides.iter()) {
+            if let Some(ref list) = overrides.rustc_args {
+                args.push((format!("targets.'{}'.rustc-args", target), list.clone()));
+            }
+            if let Some(ref list) = overrides.rustdoc_args {
+                args.push((format!("targets.'{}'.rustdoc-args", target), list.clone()));
+            }
+        }
+        for (target, list) in &self.per_target_rustdoc_args {
+            args.push((format!("'{}'.rustdoc-args", target), list.clone()));
+        }
+        for (channel, channel_args) in &self.channel_args {
+            if let Some(ref list) = channel_args.rustc_args {
+                args.push((format!("{}.rustc-args", channel), list.clone()));
+            }
+            if let Some(ref list) = channel_args.rustdoc_args {
+                args.push((format!("{}.rustdoc-args", channel), list.clone()));
+            }
+        }
+        for (name, value) in self.env.iter().flat_map(|env| env.iter()) {
+            if is_reserved_env_var(name) {
+                return Err(format_err!("`{}` can't be set in env", name));
+            }
+            args.push((format!("env.{}", name), vec![value.clone()]));
+        }
+
+        for (key, list) in args {
+            if let Some(denied) = find_denied_arg(&list) {
+                return Err(format_err!("`{}` is not allowed in {}", denied, key));
+            }
+        }
+        Ok(())
+    }
+
+
+    /// Checks `default-target` and `targets` are well-formed target triples.
+    ///
+    /// A target triple must be in `<arch>-<vendor>-<os>[-<abi>]` form.
+    pub fn validate_targets(&self) -> Result<()> {
+        let targets = self.default_target.iter()
+            .chain(self.targets.iter().flat_map(|t| t.iter()))
+            .filter(|t| *t != ALL_TARGETS);
+        for target in targets {
+            if !is_target_triple(target) {
+                return Err(MetadataError::InvalidTarget(target.clone()).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+
+
+/// Builds `Metadata` without a manifest.
+///
+/// ```text
+/// let metadata = MetadataBuilder::new()
+///     .features(&["feature1"])
+///     .default_target("x86_64-apple-darwin")
+///     .build();
+/// ```
+#[derive(Default)]
+pub struct MetadataBuilder {
+    metadata: Metadata,
+}
+
+
+fn owned(values: &[&str]) -> Option<Vec<String>> {
+    Some(values.iter().map(|v| v.to_string()).collect())
+}
+
+
+impl MetadataBuilder {
+    pub fn new() -> MetadataBuilder {
+        MetadataBuilder::default()
+    }
+
+
+    /// Marks a key as set, like it is set in the manifest.
+    fn set(mut self, key: &str) -> MetadataBuilder {
+        self.metadata.set_keys.insert(key.to_owned());
+        self.metadata.has_table = true;
+        self
+    }
+
+
+    pub fn features(mut self, features: &[&str]) -> MetadataBuilder {
+        self.metadata.features = owned(features);
+        self.set("features")
+    }
+
+
+    pub fn all_features(mut self, all_features: bool) -> MetadataBuilder {
+        self.metadata.all_features = all_features;
+        self.set("all-features")
+    }
+
+
+    pub fn no_default_features(mut self, no_default_features: bool) -> MetadataBuilder {
+        self.metadata.no_default_features = no_default_features;
+        self.set("no-default-features")
+    }
+
+
+    pub fn no_deps(mut self, no_deps: bool) -> MetadataBuilder {
+        self.metadata.no_deps = no_deps;
+        self.set("no-deps")
+    }
+
+
+    pub fn default_target(mut self, default_target: &str) -> MetadataBuilder {
+        self.metadata.default_target = Some(default_target.to_owned());
+        self.set("default-target")
+    }
+
+
+    pub fn targets(mut self, targets: &[&str]) -> MetadataBuilder {
+        self.metadata.targets = owned(targets);
+        self.set("targets")
+    }
+
+
+    pub fn cargo_args(mut self, cargo_args: &[&str]) -> MetadataBuilder {
+        self.metadata.cargo_args = owned(cargo_args);
+        self.set("cargo-args")
+    }
+
+
+    pub fn rustc_args(mut self, rustc_args: &[&str]) -> MetadataBuilder {
+        self.metadata.rustc_args = owned(rustc_args);
+        self.set("rustc-args")
+    }
+
+
+    pub fn rustdoc_args(mut self, rustdoc_args: &[&str]) -> MetadataBuilder {
+        self.metadata.rustdoc_args = owned(rustdoc_args);
+        self.set("rustdoc-args")
+    }
+
+
+    pub fn dependencies(mut self, dependencies: &[&str]) -> MetadataBuilder {
+        self.metadata.dependencies = owned(dependencies);
+        self.set("dependencies")
+    }
+
+
+    pub fn toolchain(mut self, toolchain: &str) -> MetadataBuilder {
+        self.metadata.toolchain = Some(toolchain.to_owned());
+        self.set("toolchain")
+    }
+
+
+    /// Sets `build` field, named `skip_build` to not clash with `MetadataBuilder::build`.
+    pub fn skip_build(mut self, skip_build: bool) -> MetadataBuilder {
+        self.metadata.build = !skip_build;
+        self.set("build")
+    }
+
+
+    /// Returns the built metadata.
+    ///
+    /// Metadata is not validated, call `Metadata::validate` if needed.
+    pub fn build(self) -> Metadata {
+        self.metadata
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    extern crate env_logger;
+    extern crate tempdir;
+    use super::{Metadata, MetadataBuilder, MetadataWarning, FieldChange, FeatureRef, CfgExpr,
+                suggest_key, classify_feature, MAX_LIST_LENGTH, MAX_LIST_BYTES};
+    use toml;
+
+    fn strings(s: &[&str]) -> Vec<String> {
+        s.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_cratesfyi_metadata() {
+        let _ = env_logger::try_init();
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            features = [ "feature1", "feature2" ]
+            all-features = true
+            no-default-features = true
+            default-target = "x86_64-unknown-linux-gnu"
+            rustc-args = [ "--example-rustc-arg" ]
+            rustdoc-args = [ "--example-rustdoc-arg" ]
+            dependencies = [ "example-system-dependency" ]
+        "#;
+
+        let metadata = Metadata::from_str(manifest).unwrap();
+
+        let mut expected = MetadataBuilder::new()
+            .features(&["feature1", "feature2"])
+            .all_features(true)
+            .no_default_features(true)
+            .default_target("x86_64-unknown-linux-gnu")
+            .rustc_args(&["--example-rustc-arg"])
+            .rustdoc_args(&["--example-rustdoc-arg"])
+            .dependencies(&["example-system-dependency"])
+            .build();
+        expected.dependency_specs = Some(vec![("example-system-dependency".to_owned(),
+                                               "*".to_owned())].into_iter().collect());
+        expected.raw = Some(toml::from_str::<toml::Value>(manifest).unwrap()
+            ["package"]["metadata"]["docs"]["rs"].as_table().unwrap().clone());
+        assert_eq!(metadata, expected);
+    }
+
+    #[test]
+    fn test_default() {
+        let metadata = Metadata::default();
+        assert!(metadata.features.is_none());
+        assert!(metadata.target_features.is_empty());
+        assert!(metadata.feature_matrix.is_none());
+        assert!(!metadata.all_features);
+        assert!(metadata.features_doc.is_none());
+        assert!(!metadata.no_default_features);
+        assert!(metadata.default_features.is_none());
+        assert!(!metadata.no_deps);
+        assert!(metadata.exclude_deps.is_none());
+        assert!(metadata.default_target.is_none());
+        assert!(metadata.targets.is_none());
+        assert!(metadata.exclude_targets.is_none());
+        assert!(metadata.cargo_args.is_none());
+        assert!(metadata.rustc_args.is_none());
+        assert!(metadata.rustdoc_args.is_none());
+        assert!(metadata.cfg.is_none());
+        assert!(metadata.channel_args.is_empty());
+        assert!(metadata.rustdoc_header.is_none());
+        assert!(metadata.readme.is_none());
+        assert!(metadata.rustdoc_map.is_none());
+        assert!(metadata.dependencies.is_none());
+        assert!(metadata.dependency_specs.is_none());
+        assert!(metadata.dependencies_map.is_none());
+        assert!(metadata.env.is_none());
+        assert!(metadata.toolchain.is_none());
+        assert!(metadata.build);
+        assert!(metadata.build_timeout.is_none());
+        assert!(metadata.priority.is_none());
+        assert!(metadata.unknown_keys.is_empty());
+        assert!(metadata.string_keys.is_empty());
+        assert!(metadata.skipped_entries.is_empty());
+    }
+
+    #[test]
+    fn test_target_overrides() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            features = [ "feature1", "feature2" ]
+            rustdoc-args = [ "--example-rustdoc-arg" ]
+
+            [package.metadata.docs.rs.targets.x86_64-unknown-linux-gnu]
+
+            [package.metadata.docs.rs.targets.wasm32-unknown-unknown]
+            features = [ "wasm" ]
+            rustc-args = [ "--example-wasm-rustc-arg" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        let targets = metadata.targets.clone().unwrap();
+        assert_eq!(targets, vec!["wasm32-unknown-unknown".to_owned(),
+                                 "x86_64-unknown-linux-gnu".to_owned()]);
+        assert!(metadata.unknown_keys.is_empty());
+
+        let linux = metadata.resolve("x86_64-unknown-linux-gnu");
+        assert_eq!(linux.features.unwrap(), vec!["feature1".to_owned(), "feature2".to_owned()]);
+        assert!(linux.rustc_args.is_none());
+
+        let wasm = metadata.resolve("wasm32-unknown-unknown");
+        assert_eq!(wasm.features.unwrap(), vec!["wasm".to_owned()]);
+        assert_eq!(wasm.rustc_args.unwrap(), vec!["--example-wasm-rustc-arg".to_owned()]);
+        assert_eq!(wasm.rustdoc_args.unwrap(), vec!["--example-rustdoc-arg".to_owned()]);
+    }
+
+    #[test]
+    fn test_cfg_target_overrides() {
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            features = [ "feature1" ]
+
+            [package.metadata.docs.rs.targets.x86_64-pc-windows-msvc]
+            [package.metadata.docs.rs.targets.i686-pc-windows-gnu]
+            rustc-args = [ "--example-i686-rustc-arg" ]
+            [package.metadata.docs.rs.targets.x86_64-unknown-linux-gnu]
+
+            [package.metadata.docs.rs.targets.'cfg(windows)']
+            features = [ "winapi" ]
+            rustc-args = [ "--example-windows-rustc-arg" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.targets.clone().unwrap(),
+                   vec!["i686-pc-windows-gnu".to_owned(), "x86_64-pc-windows-msvc".to_owned(),
+                        "x86_64-unknown-linux-gnu".to_owned()]);
+        assert!(metadata.validate_targets().is_ok());
+        assert!(metadata.warnings().is_empty());
+
+        let windows = metadata.resolve("x86_64-pc-windows-msvc");
+        assert_eq!(windows.features.unwrap(), vec!["winapi".to_owned()]);
+        assert_eq!(windows.rustc_args.unwrap(), vec!["--example-windows-rustc-arg".to_owned()]);
+
+        // overrides of the target itself win
+        let i686 = metadata.resolve("i686-pc-windows-gnu");
+        assert_eq!(i686.features.unwrap(), vec!["winapi".to_owned()]);
+        assert_eq!(i686.rustc_args.unwrap(), vec!["--example-i686-rustc-arg".to_owned()]);
+
+        let linux = metadata.resolve("x86_64-unknown-linux-gnu");
+        assert_eq!(linux.features.unwrap(), vec!["feature1".to_owned()]);
+        assert!(linux.rustc_args.is_none());
+
+        let value = metadata.to_toml();
+        assert_eq!(Metadata::from_str(&value.to_string()).unwrap().to_toml(), value);
+
+        let manifest = r#"
+            [package.metadata.docs.rs.targets.'cfg(windows'
+        "#;
+        assert!(Metadata::from_str(manifest).is_err());
+        let manifest = r#"
+            [package.metadata.docs.rs.targets.'cfg(not(unix, windows))']
+        "#;
+        assert!(Metadata::from_str(manifest).is_err());
+    }
+
+    #[test]
+    fn test_cfg_expr() {
+        fn matches(expr: &str, target: &str) -> bool {
+            CfgExpr::parse(expr).unwrap().matches(target)
+        }
+
+        assert!(matches("cfg(windows)", "x86_64-pc-windows-msvc"));
+        assert!(!matches("cfg(windows)", "x86_64-unknown-linux-gnu"));
+        assert!(matches("cfg(unix)", "x86_64-unknown-linux-gnu"));
+        assert!(matches("cfg(unix)", "arm-linux-androideabi"));
+        assert!(!matches("cfg(unix)", "wasm32-unknown-unknown"));
+        assert!(matches("cfg(target_os = \"macos\")", "x86_64-apple-darwin"));
+        assert!(matches("cfg(target_os = \"android\")", "aarch64-linux-android"));
+        assert!(matches("cfg(target_arch = \"x86\")", "i686-pc-windows-msvc"));
+        assert!(matches("cfg(target_arch = \"arm\")", "armv7-unknown-linux-gnueabihf"));
+        assert!(matches("cfg(target_env = \"musl\")", "x86_64-unknown-linux-musl"));
+        assert!(matches("cfg(target_family = \"windows\")", "i686-pc-windows-gnu"));
+        assert!(matches("cfg(target_vendor = \"apple\")", "aarch64-apple-ios"));
+        assert!(matches("cfg(all(unix, target_arch = \"x86_64\"))", "x86_64-unknown-freebsd"));
+        assert!(!matches("cfg(all(unix, target_arch = \"x86_64\"))", "i686-unknown-linux-gnu"));
+        assert!(matches("cfg(any(windows, target_os = \"macos\"))", "x86_64-apple-darwin"));
+        assert!(matches("cfg(not(windows))", "wasm32-unknown-unknown"));
+        assert!(matches("cfg(all())", "wasm32-unknown-unknown"));
+        assert!(!matches("cfg(any())", "wasm32-unknown-unknown"));
+
+        assert!(CfgExpr::parse("windows").is_err());
+        assert!(CfgExpr::parse("cfg(target_os = macos)").is_err());
+        assert!(CfgExpr::parse("cfg(windows) extra").is_err());
+        assert!(CfgExpr::parse("cfg(xor(unix, windows))").is_err());
+        assert!(CfgExpr::parse("cfg(target_os = \"macos)").is_err());
+    }
+
+    #[test]
+    fn test_per_target_rustdoc_args() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            rustdoc-args = [ "--cfg", "docsrs" ]
+
+            [package.metadata.docs.rs.'wasm32-unknown-unknown']
+            rustdoc-args = [ "--html-in-header", "wasm.html" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(metadata.unknown_keys.is_empty());
+        assert_eq!(metadata.rustdoc_args_for("wasm32-unknown-unknown"),
+                   vec!["--cfg".to_owned(), "docsrs".to_owned(),
+                        "--html-in-header".to_owned(), "wasm.html".to_owned()]);
+        assert_eq!(metadata.rustdoc_args_for("x86_64-unknown-linux-gnu"),
+                   vec!["--cfg".to_owned(), "docsrs".to_owned()]);
+
+        let manifest = r#"
+            [package.metadata.docs.rs.x86_64-pc-windows-msvc]
+            features = [ "winapi" ]
+            rustdoc-args = [ "--cfg", "windows_docs" ]
+
+            [package.metadata.docs.rs.'cfg(unix)']
+            rustc-args = [ "--cfg", "unix_docs" ]
+
+            [package.metadata.docs.rs.enviroment]
+            PKG_CONFIG_PATH = "/usr/lib/pkgconfig"
+
+            [package.metadata.docs.rs.rustdoc-mapp]
+            serde = "https://docs.serde.rs/"
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.features_for("x86_64-pc-windows-msvc"),
+                   Some(vec!["winapi".to_owned()]));
+        assert_eq!(metadata.rustdoc_args_for("x86_64-pc-windows-msvc"),
+                   vec!["--cfg".to_owned(), "windows_docs".to_owned()]);
+        assert_eq!(metadata.cfg_target_overrides["cfg(unix)"].rustc_args,
+                   Some(vec!["--cfg".to_owned(), "unix_docs".to_owned()]));
+        assert_eq!(metadata.unknown_keys, vec!["enviroment".to_owned(), "rustdoc-mapp".to_owned()]);
+        assert!(!metadata.per_target_rustdoc_args.contains_key("enviroment"));
+        assert!(metadata.warnings().contains(&MetadataWarning::UnknownKey {
+            key: "rustdoc-mapp".to_owned(),
+            suggestion: Some("rustdoc-map".to_owned()),
+        }));
+    }
+
+    #[test]
+    fn test_args_for_channel() {
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            rustc-args = [ "--example-rustc-arg" ]
+            rustdoc-args = [ "--cfg", "docsrs" ]
+
+            [package.metadata.docs.rs.nightly]
+            rustdoc-args = [ "--cfg", "docsrs_nightly" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(metadata.unknown_keys.is_empty());
+        assert!(metadata.per_target_rustdoc_args.is_empty());
+
+        let (rustc_args, rustdoc_args) = metadata.args_for_channel("nightly-2019-02-01");
+        assert_eq!(rustc_args, vec!["--example-rustc-arg".to_owned()]);
+        assert_eq!(rustdoc_args, vec!["--cfg".to_owned(), "docsrs".to_owned(),
+                                      "--cfg".to_owned(), "docsrs_nightly".to_owned()]);
+
+        let (rustc_args, rustdoc_args) = metadata.args_for_channel("stable");
+        assert_eq!(rustc_args, vec!["--example-rustc-arg".to_owned()]);
+        assert_eq!(rustdoc_args, vec!["--cfg".to_owned(), "docsrs".to_owned()]);
+
+        let parsed = Metadata::from_str(&metadata.to_toml().to_string()).unwrap();
+        assert_eq!(parsed.channel_args, metadata.channel_args);
+    }
+
+    #[test]
+    fn test_resolve_build() {
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            rustc-args = [ "--cfg", "global" ]
+            rustdoc-args = [ "--cfg", "docsrs" ]
+
+            [package.metadata.docs.rs.wasm32-unknown-unknown]
+            rustc-args = [ "--cfg", "wasm" ]
+
+            [package.metadata.docs.rs.targets.x86_64-pc-windows-msvc]
+            rustdoc-args = [ "--cfg", "windows" ]
+
+            [package.metadata.docs.rs.nightly]
+            rustdoc-args = [ "--cfg", "nightly" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+
+        let wasm = metadata.resolve_build("wasm32-unknown-unknown", "nightly");
+        assert_eq!(wasm.rustc_args, Some(strings(&["--cfg", "wasm"])));
+        assert_eq!(wasm.rustdoc_args, Some(strings(&["--cfg", "docsrs", "--cfg", "nightly"])));
+
+        let windows = metadata.resolve_build("x86_64-pc-windows-msvc", "stable");
+        assert_eq!(windows.rustc_args, Some(strings(&["--cfg", "global"])));
+        assert_eq!(windows.default_rustdoc_args(),
+                   strings(&["--cfg", "docsrs", "--cfg", "windows"]));
+
+        let default = Metadata::default().resolve_build("x86_64-unknown-linux-gnu", "nightly");
+        assert_eq!(default.rustc_args, None);
+        assert_eq!(default.rustdoc_args, None);
+    }
+
+    #[test]
+    fn test_cargo_args() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            cargo-args = [ "-Zbuild-std", "--config=build.jobs=1" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        let cargo_args = metadata.cargo_args.unwrap();
+        assert_eq!(cargo_args.len(), 2);
+        assert_eq!(cargo_args[0], "-Zbuild-std".to_owned());
+        assert_eq!(cargo_args[1], "--config=build.jobs=1".to_owned());
+    }
+
+    #[test]
+    fn test_env() {
+        use std::collections::BTreeMap;
+
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs.env]
+            PKG_CONFIG_PATH = "/usr/lib/pkgconfig"
+            OPENSSL_DIR = "/usr/lib/ssl"
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(metadata.unknown_keys.is_empty());
+        let env = metadata.env.unwrap();
+        assert_eq!(env.len(), 2);
+        assert_eq!(env["PKG_CONFIG_PATH"], "/usr/lib/pkgconfig".to_owned());
+        assert_eq!(env["OPENSSL_DIR"], "/usr/lib/ssl".to_owned());
+
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs.env]
+            PKG_CONFIG_PATH = "/usr/lib/pkgconfig"
+            OPENSSL_DIR = "/usr/lib/ssl"
+            JOBS = 4
+        "#;
+        assert!(Metadata::from_str(manifest).is_err());
+
+        let mut metadata = Metadata::default();
+        let mut env = BTreeMap::new();
+        env.insert("OPENSSL_DIR".to_owned(), "/usr/lib/ssl".to_owned());
+        metadata.env = Some(env.clone());
+        assert!(metadata.sanitize_args().is_ok());
+        for name in &["RUSTFLAGS", "RUSTDOCFLAGS", "RUSTC_WRAPPER", "CARGO_TARGET_DIR",
+                      "CARGO_HOME", "cargo_home", "PATH", "LD_PRELOAD", "RUSTUP_TOOLCHAIN"] {
+            let mut env = env.clone();
+            env.insert(name.to_string(), "value".to_owned());
+            metadata.env = Some(env);
+            match metadata.sanitize_args() {
+                Ok(_) => panic!("{} allowed", name),
+                Err(e) => assert_eq!(e.to_string(), format!("`{}` can't be set in env", name)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_features_for() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            features = [ "feature1" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.features_for("x86_64-unknown-linux-gnu"),
+                   Some(vec!["feature1".to_owned()]));
+        assert_eq!(metadata.features_for("wasm32-unknown-unknown"),
+                   Some(vec!["feature1".to_owned()]));
+
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs.features]
+            x86_64-unknown-linux-gnu = [ "tokio" ]
+            wasm32-unknown-unknown = []
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(metadata.features.is_none());
+        assert!(metadata.unknown_keys.is_empty());
+        assert_eq!(metadata.features_for("x86_64-unknown-linux-gnu"),
+                   Some(vec!["tokio".to_owned()]));
+        assert_eq!(metadata.features_for("wasm32-unknown-unknown"), Some(Vec::new()));
+        assert_eq!(metadata.features_for("x86_64-apple-darwin"), None);
+    }
+
+    #[test]
+    fn test_normalize_features() {
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\nfeatures = []").unwrap();
+        assert_eq!(metadata.features, Some(Vec::new()));
+
+        let metadata = Metadata::from_str(
+            "[package.metadata.docs.rs]\nfeatures = [ \"b\", \" a \", \"b\", \"a\" ]").unwrap();
+        assert_eq!(metadata.features, Some(vec!["b".to_owned(), "a".to_owned()]));
+    }
+
+    #[test]
+    fn test_feature_matrix() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            feature-matrix = [ [ "backend-tokio" ], [ "backend-async-std", "tls" ] ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        let jobs = metadata.matrix_jobs();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].features, Some(strings(&["backend-tokio"])));
+        assert_eq!(jobs[1].features, Some(strings(&["backend-async-std", "tls"])));
+        assert!(jobs.iter().all(|job| job.feature_matrix.is_none()));
+
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            features = [ "feature1", "feature2" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        let jobs = metadata.matrix_jobs();
+        assert_eq!(jobs, vec![metadata]);
+        assert_eq!(jobs[0].feature_flags(),
+                   strings(&["--features", "feature1", "--features", "feature2"]));
+
+        let metadata = MetadataBuilder::new().all_features(true).build();
+        let jobs = metadata.matrix_jobs();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].feature_flags(), strings(&["--all-features"]));
+
+        let metadata = MetadataBuilder::new().no_default_features(true).build();
+        let jobs = metadata.matrix_jobs();
+        assert_eq!(jobs[0].feature_flags(), strings(&["--no-default-features"]));
+
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            all-features = true
+            feature-matrix = [ [ "sync" ] ]
+        "#;
+        let jobs = Metadata::from_str(manifest).unwrap().matrix_jobs();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].feature_flags(), strings(&["--features", "sync"]));
+
+        assert_eq!(Metadata::default().matrix_jobs(), vec![Metadata::default()]);
+    }
+
+    #[test]
+    fn test_toolchain() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            toolchain = "nightly"
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.toolchain, Some("nightly".to_owned()));
+        assert!(metadata.validate().is_ok());
+
+        let mut metadata = Metadata::default();
+        metadata.toolchain = Some("nightly-2019-02-01".to_owned());
+        assert!(metadata.validate().is_ok());
+
+        metadata.toolchain = Some("1.31".to_owned());
+        assert!(metadata.validate().is_ok());
+        metadata.toolchain = Some("1.31.1".to_owned());
+        assert!(metadata.validate().is_ok());
+
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\nrust-version = \"1.31\"")
+            .unwrap();
+        assert_eq!(metadata.toolchain, Some("1.31".to_owned()));
+        assert!(metadata.unknown_keys.is_empty());
+
+        let mut metadata = Metadata::default();
+        for toolchain in &["banana", "nightly-2019-2-1", "nightly-yesterday", "stable-",
+                           "1", "1.", "1.31.1.1", "1.x"] {
+            metadata.toolchain = Some(toolchain.to_string());
+            assert!(metadata.validate().is_err(), "{}", toolchain);
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            all-features = true
+        "#;
+        assert!(Metadata::from_str(manifest).unwrap().build);
+
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            build = false
+        "#;
+        assert!(!Metadata::from_str(manifest).unwrap().build);
+    }
+
+    #[test]
+    fn test_rustdoc_header() {
+        use std::fs::{self, File};
+        use std::os::unix::fs::symlink;
+
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            rustdoc-header = "docs/header.html"
+        "#;
+        let dir = tempdir::TempDir::new("cratesfyi-rustdoc-header").unwrap();
+        let root = dir.path().join("crate");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        File::create(root.join("docs/header.html")).unwrap();
+        File::create(dir.path().join("header.html")).unwrap();
+
+        let mut metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.rustdoc_header_path(&root).unwrap(),
+                   Some(root.canonicalize().unwrap().join("docs/header.html")));
+
+        metadata.rustdoc_header = Some("../header.html".to_owned());
+        assert!(metadata.rustdoc_header_path(&root).is_err());
+
+        metadata.rustdoc_header = Some("docs/../../header.html".to_owned());
+        assert!(metadata.rustdoc_header_path(&root).is_err());
+
+        metadata.rustdoc_header = Some("/etc/header.html".to_owned());
+        assert!(metadata.rustdoc_header_path(&root).is_err());
+
+        symlink(dir.path().join("header.html"), root.join("docs/link.html")).unwrap();
+        metadata.rustdoc_header = Some("docs/link.html".to_owned());
+        assert_eq!(metadata.rustdoc_header_path(&root).unwrap_err().to_string(),
+                   "rustdoc-header `docs/link.html` must be a path inside the crate");
+
+        metadata.rustdoc_header = Some("docs/missing.html".to_owned());
+        assert_eq!(metadata.rustdoc_header_path(&root).unwrap_err().to_string(),
+                   "rustdoc-header `docs/missing.html` does not exist");
+
+        metadata.rustdoc_header = None;
+        assert_eq!(metadata.rustdoc_header_path(&root).unwrap(), None);
+    }
+
+    #[test]
+    fn test_rustdoc_map() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs.rustdoc-map]
+            zeta-dependency = "https://zeta.example.com/docs/"
+            alpha = "http://alpha.example.com/"
+            broken = "not a url"
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.rustdoc_map_args(),
+                   vec!["--extern-html-root-url".to_owned(),
+                        "alpha=http://alpha.example.com/".to_owned(),
+                        "--extern-html-root-url".to_owned(),
+                        "zeta_dependency=https://zeta.example.com/docs/".to_owned()]);
+        let warnings = metadata.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].to_string().contains("'broken'"), "{}", warnings[0]);
+
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs.rustdoc-map]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(metadata.rustdoc_map.unwrap().is_empty());
+        assert!(Metadata::default().rustdoc_map_args().is_empty());
+    }
+
+    #[test]
+    fn test_rustdoc_flags() {
+        let mut metadata = Metadata::default();
+        assert_eq!(metadata.rustdoc_flags(), strings(&["--cfg", "docsrs"]));
+
+        metadata.all_features = true;
+        assert_eq!(metadata.rustdoc_flags(), strings(&["--all-features", "--cfg", "docsrs"]));
+
+        let mut metadata = Metadata::default();
+        metadata.no_default_features = true;
+        metadata.features = Some(strings(&["feature1", "feature2"]));
+        metadata.rustdoc_args = Some(strings(&["--example-rustdoc-arg"]));
+        assert_eq!(metadata.rustdoc_flags(),
+                   strings(&["--no-default-features",
+                             "--features", "feature1",
+                             "--features", "feature2",
+                             "--cfg", "docsrs",
+                             "--example-rustdoc-arg"]));
+    }
+
+    #[test]
+    fn test_default_rustdoc_args() {
+        let metadata = Metadata::default();
+        assert_eq!(metadata.default_rustdoc_args(), vec!["--cfg".to_owned(), "docsrs".to_owned()]);
+
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            cfg = "custom_flag"
+            rustdoc-args = [ "--example-rustdoc-arg" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(metadata.warnings().is_empty());
+        assert_eq!(metadata.default_rustdoc_args(),
+                   vec!["--cfg".to_owned(), "docsrs".to_owned(),
+                        "--cfg".to_owned(), "custom_flag".to_owned(),
+                        "--example-rustdoc-arg".to_owned()]);
+    }
+
+    #[test]
+    fn test_effective_features() {
+        let mut metadata = Metadata::default();
+        assert_eq!(metadata.effective_features(), (false, Vec::new()));
+
+        metadata.no_default_features = true;
+        metadata.features = Some(vec!["feature1".to_owned()]);
+        assert_eq!(metadata.effective_features(), (true, vec!["feature1".to_owned()]));
+
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            all-features = true
+            features-doc = "doc-all"
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.effective_features(), (true, vec!["doc-all".to_owned()]));
+        assert!(!metadata.uses_all_features());
+        assert_eq!(metadata.rustdoc_flags(),
+                   vec!["--no-default-features".to_owned(),
+                        "--features".to_owned(), "doc-all".to_owned(),
+                        "--cfg".to_owned(), "docsrs".to_owned()]);
+    }
+
+    #[test]
+    fn test_feature_flags() {
+        let metadata = MetadataBuilder::new().build();
+        assert!(metadata.feature_flags().is_empty());
+
+        let metadata = MetadataBuilder::new().features(&["feature1"]).build();
+        assert_eq!(metadata.feature_flags(), strings(&["--features", "feature1"]));
+
+        let metadata = MetadataBuilder::new()
+            .features(&["feature1"])
+            .no_default_features(true)
+            .build();
+        assert_eq!(metadata.feature_flags(),
+                   strings(&["--no-default-features", "--features", "feature1"]));
+
+        let metadata = MetadataBuilder::new().all_features(true).build();
+        assert_eq!(metadata.feature_flags(), strings(&["--all-features"]));
+    }
+
+    #[test]
+    fn test_to_cargo_args() {
+        let linux = "x86_64-unknown-linux-gnu";
+        assert_eq!(Metadata::default().to_cargo_args(linux), strings(&["--target", linux]));
+
+        let metadata = MetadataBuilder::new()
+            .features(&["feature1", "feature2"])
+            .no_default_features(true)
+            .no_deps(true)
+            .cargo_args(&["-Zbuild-std"])
+            .build();
+        assert_eq!(metadata.to_cargo_args(linux),
+                   strings(&["-Zbuild-std", "--no-default-features", "--features", "feature1",
+                             "--features", "feature2", "--no-deps", "--target", linux]));
+
+        let metadata = MetadataBuilder::new()
+            .all_features(true)
+            .no_default_features(true)
+            .build();
+        assert_eq!(metadata.to_cargo_args(linux),
+                   strings(&["--all-features", "--no-default-features", "--target", linux]));
+
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            features = [ "feature1" ]
+
+            [package.metadata.docs.rs.targets.x86_64-unknown-linux-gnu]
+            [package.metadata.docs.rs.targets.wasm32-unknown-unknown]
+            features = [ "wasm" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.to_cargo_args(linux),
+                   strings(&["--features", "feature1", "--target", linux]));
+        assert_eq!(metadata.to_cargo_args("wasm32-unknown-unknown"),
+                   strings(&["--features", "wasm", "--target", "wasm32-unknown-unknown"]));
+    }
+
+    #[test]
+    fn test_empty_features_without_default_features() {
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            features = []
+            no-default-features = true
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.features, Some(Vec::new()));
+        assert!(metadata.validate().is_ok());
+        assert_eq!(metadata.effective_features(), (true, Vec::new()));
+        assert_eq!(metadata.feature_flags(), vec!["--no-default-features".to_owned()]);
+
+        let value = metadata.to_toml();
+        let table = value["package"]["metadata"]["docs"]["rs"].as_table().unwrap();
+        assert_eq!(table.get("features").and_then(|f| f.as_array()).map(|f| f.len()), Some(0));
+        assert_eq!(Metadata::from_str(&value.to_string()).unwrap().features, Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_build_timeout() {
+        use std::time::Duration;
+        use super::MAX_BUILD_TIMEOUT;
+
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\nbuild-timeout = \"15min\"")
+            .unwrap();
+        assert_eq!(metadata.build_timeout, Some(Duration::from_secs(15 * 60)));
+
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\nbuild-timeout = \"1h 30s\"")
+            .unwrap();
+        assert_eq!(metadata.build_timeout, Some(Duration::from_secs(60 * 60 + 30)));
+
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\nbuild-timeout = 600")
+            .unwrap();
+        assert_eq!(metadata.build_timeout, Some(Duration::from_secs(600)));
+
+        assert_eq!(metadata.build_timeout_seconds, Some(600));
+        assert!(metadata.warnings().is_empty());
+
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\nbuild-timeout = \"10h\"")
+            .unwrap();
+        assert_eq!(metadata.build_timeout, Some(Duration::from_secs(10 * 60 * 60)));
+        assert_eq!(metadata.build_timeout_seconds, Some(MAX_BUILD_TIMEOUT));
+        assert!(metadata.validate().is_ok());
+        assert_eq!(metadata.warnings(), vec![MetadataWarning::Clamped {
+            key: "build-timeout".to_owned(),
+            reason: "build-timeout must be at most 7200s, 36000s is clamped to 7200s".to_owned(),
+        }]);
+
+        let base = Metadata::from_str("[package.metadata.docs.rs]\nbuild-timeout = 600").unwrap();
+        assert_eq!(base.merge(metadata).build_timeout_seconds, Some(MAX_BUILD_TIMEOUT));
+
+        assert!(Metadata::default().build_timeout.is_none());
+        assert!(Metadata::default().build_timeout_seconds.is_none());
+
+        let mut metadata = Metadata::default();
+        metadata.build_timeout = Some(Duration::from_secs(0));
+        assert!(metadata.validate().is_err());
+
+        for timeout in &["0", "-10", "\"0s\"", "\"15 parsecs\"", "\"\"",
+                         "\"5000000000000000h\""] {
+            let manifest = format!("[package.metadata.docs.rs]\nbuild-timeout = {}", timeout);
+            assert!(Metadata::from_str(&manifest).is_err());
+        }
+    }
+
+    #[test]
+    fn test_max_output_size() {
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\nmax-output-size = \"500MB\"")
+            .unwrap();
+        assert_eq!(metadata.max_output_size, Some(500 * 1000 * 1000));
+        assert!(metadata.check_output_size(500 * 1000 * 1000).is_ok());
+        let err = metadata.check_output_size(500 * 1000 * 1000 + 1).unwrap_err();
+        assert_eq!(err.to_string(), "documentation is 500000001 bytes, which is larger than \
+                                     max-output-size of 500000000 bytes");
+
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\nmax-output-size = \"1GB\"")
+            .unwrap();
+        assert_eq!(metadata.max_output_size, Some(1000 * 1000 * 1000));
+
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\nmax-output-size = 2048")
+            .unwrap();
+        assert_eq!(metadata.max_output_size, Some(2048));
+
+        assert!(Metadata::default().check_output_size(u64::max_value()).is_ok());
+
+        for size in &["\"big\"", "\"500TB\"", "\"0MB\"", "\"MB\"", "0", "-1",
+                      "\"10000000000GB\""] {
+            let manifest = format!("[package.metadata.docs.rs]\nmax-output-size = {}", size);
+            assert!(Metadata::from_str(&manifest).is_err(), "{}", size);
+        }
+    }
+
+    #[test]
+    fn test_priority() {
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\npriority = 5").unwrap();
+        assert_eq!(metadata.priority, Some(5));
+        assert_eq!(metadata.build_priority(), 5);
+        assert!(metadata.validate().is_ok());
+
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\npriority = -5").unwrap();
+        assert_eq!(metadata.priority, Some(-5));
+        assert_eq!(metadata.build_priority(), -5);
+        assert!(metadata.validate().is_ok());
+
+        assert_eq!(Metadata::default().build_priority(), 0);
+
+        let mut metadata = Metadata::default();
+        metadata.priority = Some(-100);
+        assert!(metadata.validate().is_ok());
+        assert!(metadata.warnings().is_empty());
+        metadata.priority = Some(101);
+        assert!(metadata.validate().is_ok());
+        assert_eq!(metadata.build_priority(), 100);
+        assert_eq!(metadata.warnings(), vec![MetadataWarning::Clamped {
+            key: "priority".to_owned(),
+            reason: "priority must be between -100 and 100, 101 is clamped to 100".to_owned(),
+        }]);
+        metadata.priority = Some(-101);
+        assert!(metadata.validate().is_ok());
+        assert_eq!(metadata.build_priority(), -100);
+        assert_eq!(metadata.warnings().len(), 1);
+
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\npriority = 5000000000")
+            .unwrap();
+        assert_eq!(metadata.priority, Some(5000000000));
+        assert_eq!(metadata.build_priority(), 100);
+        assert_eq!(metadata.warnings()[0].to_string(),
+                   "priority must be between -100 and 100, 5000000000 is clamped to 100");
+        assert_eq!(Metadata::from_str(&metadata.to_toml().to_string()).unwrap(), metadata);
+
+        assert!(Metadata::from_str("[package.metadata.docs.rs]\npriority = \"high\"").is_err());
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        use super::{MAX_MEMORY_LIMIT, MAX_CPU_QUOTA};
+
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            memory-limit = 4096
+            cpu-quota = 2
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.memory_limit_mb, Some(4096));
+        assert_eq!(metadata.cpu_quota, Some(2));
+        assert!(metadata.validate().is_ok());
+
+        let metadata = Metadata::default();
+        assert!(metadata.memory_limit_mb.is_none());
+        assert!(metadata.cpu_quota.is_none());
+
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\nmemory-limit = 0").unwrap();
+        assert_eq!(metadata.validate().unwrap_err().to_string(),
+                   "memory-limit must be between 1 and 8192 megabytes, got 0");
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\ncpu-quota = 0").unwrap();
+        assert_eq!(metadata.validate().unwrap_err().to_string(),
+                   "cpu-quota must be between 1 and 4, got 0");
+
+        let mut metadata = Metadata::default();
+        metadata.memory_limit_mb = Some(MAX_MEMORY_LIMIT);
+        metadata.cpu_quota = Some(i64::from(MAX_CPU_QUOTA));
+        assert!(metadata.validate().is_ok());
+        metadata.memory_limit_mb = Some(MAX_MEMORY_LIMIT + 1);
+        assert!(metadata.validate().is_err());
+        metadata.memory_limit_mb = None;
+        metadata.cpu_quota = Some(i64::from(MAX_CPU_QUOTA) + 1);
+        assert!(metadata.validate().is_err());
+
+        assert!(Metadata::from_str("[package.metadata.docs.rs]\nmemory-limit = -1").is_err());
+        for cpu_quota in &[-2i64, 5000000000] {
+            let manifest = format!("[package.metadata.docs.rs]\ncpu-quota = {}", cpu_quota);
+            let metadata = Metadata::from_str(&manifest).unwrap();
+            assert_eq!(metadata.validate().unwrap_err().to_string(),
+                       format!("cpu-quota must be between 1 and 4, got {}", cpu_quota));
+        }
+    }
+
+    #[test]
+    fn test_has_metadata_table() {
+        let metadata = Metadata::from_str("[package]\nname = \"test\"").unwrap();
+        assert!(!metadata.has_metadata_table());
+        assert!(!Metadata::default().has_metadata_table());
+
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]").unwrap();
+        assert!(metadata.has_metadata_table());
+        assert!(metadata != Metadata::default());
+        assert!(metadata.validate().is_ok());
+
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\nno-deps = true").unwrap();
+        assert!(metadata.has_metadata_table());
+        assert!(Metadata::default().merge(metadata).has_metadata_table());
+    }
+
+    #[test]
+    fn test_raw() {
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            no-deps = true
+            experimental-setting = "value"
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        let raw = metadata.raw.as_ref().unwrap();
+        assert_eq!(raw.get("experimental-setting").and_then(|v| v.as_str()), Some("value"));
+        assert_eq!(raw.get("no-deps").and_then(|v| v.as_bool()), Some(true));
+
+        assert!(Metadata::from_str("[package]\nname = \"test\"").unwrap().raw.is_none());
+
+        let overlay = Metadata::from_str("[package.metadata.docs.rs]\nno-deps = false").unwrap();
+        let raw = metadata.merge(overlay).raw.unwrap();
+        assert_eq!(raw.get("experimental-setting").and_then(|v| v.as_str()), Some("value"));
+        assert_eq!(raw.get("no-deps").and_then(|v| v.as_bool()), Some(false));
+    }
+
+    #[test]
+    fn test_renamed_keys() {
+        for key in &["all_features", "All-Features", "ALL_FEATURES"] {
+            let manifest = format!("[package.metadata.docs.rs]\n{} = true", key);
+            let metadata = Metadata::from_str(&manifest).unwrap();
+            assert!(metadata.all_features);
+            assert!(metadata.is_set("all-features"));
+            assert!(metadata.unknown_keys.is_empty());
+            assert_eq!(metadata.warnings(), vec![MetadataWarning::Deprecated {
+                key: key.to_string(),
+                replacement: "all-features".to_owned(),
+            }]);
+        }
+
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            all_features = true
+            all-features = false
+        "#;
+        let err = Metadata::from_str(manifest).unwrap_err();
+        assert!(err.to_string().contains("`all_features` and `all-features`"), "{}", err);
+
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            all_features = true
+            All-Features = false
+        "#;
+        assert!(Metadata::from_str(manifest).is_err());
+
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\nall-features = true")
+            .unwrap();
+        assert!(metadata.renamed_keys.is_empty());
+
+        let table: toml::Value = toml::from_str("all_features = true\nall-feature = true")
+            .unwrap();
+        assert_eq!(Metadata::unknown_keys(table.as_table().unwrap()),
+                   vec!["all-feature".to_owned()]);
+    }
+
+    #[test]
+    fn test_validate_limits() {
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            features = [ "feature1", "feature2" ]
+            rustc-args = [ "--example-rustc-arg" ]
+            rustdoc-args = [ "--example-rustdoc-arg" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(metadata.validate().is_ok());
+        assert!(metadata.validate_limits(2, 100).is_ok());
+
+        let err = metadata.validate_limits(1, 100).unwrap_err();
+        assert_eq!(err.to_string(), "features has 2 entries, at most 1 are allowed");
+        let err = metadata.validate_limits(2, 16).unwrap_err();
+        assert_eq!(err.to_string(), "rustc-args is 19 bytes long, at most 16 bytes are allowed");
+
+        let mut metadata = Metadata::default();
+        metadata.rustc_args = Some(vec!["--cfg".to_owned(); MAX_LIST_LENGTH + 1]);
+        let err = metadata.validate().unwrap_err();
+        assert!(err.to_string().starts_with("rustc-args has 257 entries"), "{}", err);
+
+        metadata.rustc_args = Some(vec!["x".repeat(MAX_LIST_BYTES + 1)]);
+        let err = metadata.validate().unwrap_err();
+        assert!(err.to_string().starts_with("rustc-args is 16385 bytes long"), "{}", err);
+
+        // rustc-args was accepted before lists were limited, new keys are still rejected
+        assert!(metadata.validate_build().is_ok());
+        assert_eq!(metadata.warnings().len(), 1);
+        metadata.cargo_args = Some(vec!["--cfg".to_owned(); MAX_LIST_LENGTH + 1]);
+        let err = metadata.validate_build().unwrap_err();
+        assert!(err.to_string().starts_with("cargo-args has 257 entries"), "{}", err);
+    }
+
+    #[test]
+    fn test_no_deps() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            all-features = true
+        "#;
+        assert!(!Metadata::from_str(manifest).unwrap().no_deps);
+
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            no-deps = true
+        "#;
+        assert!(Metadata::from_str(manifest).unwrap().no_deps);
+    }
+
+    #[test]
+    fn test_docsrs_cfg() {
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\nno-deps = true").unwrap();
+        assert!(metadata.wants_docsrs_cfg());
+        assert_eq!(metadata.default_rustdoc_args(), vec!["--cfg".to_owned(), "docsrs".to_owned()]);
+        assert!(!metadata.all_features_or_cfg());
+
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            docsrs-cfg = false
+            cfg = [ "example_cfg" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(!metadata.wants_docsrs_cfg());
+        assert_eq!(metadata.default_rustdoc_args(),
+                   vec!["--cfg".to_owned(), "example_cfg".to_owned()]);
+        assert!(metadata.all_features_or_cfg());
+
+        let metadata = MetadataBuilder::new().all_features(true).build();
+        assert!(metadata.all_features_or_cfg());
+    }
+
+    #[test]
+    fn test_document_private_items() {
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\nno-deps = true").unwrap();
+        assert!(!metadata.document_private_items);
+        assert!(!metadata.default_rustdoc_args().contains(&"--document-private-items".to_owned()));
+
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            document-private-items = true
+            rustdoc-args = [ "--example-rustdoc-arg" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(metadata.document_private_items);
+        assert_eq!(metadata.default_rustdoc_args(),
+                   vec!["--cfg".to_owned(), "docsrs".to_owned(),
+                        "--document-private-items".to_owned(),
+                        "--example-rustdoc-arg".to_owned()]);
+    }
+
+    #[test]
+    fn test_document_examples() {
+        let linux = "x86_64-unknown-linux-gnu";
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\nno-deps = true").unwrap();
+        assert!(!metadata.document_examples);
+        assert!(!metadata.to_cargo_args(linux).contains(&"--examples".to_owned()));
+
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            document-examples = true
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(metadata.document_examples);
+        assert!(!metadata.no_deps);
+        assert_eq!(metadata.to_cargo_args(linux),
+                   vec!["--examples".to_owned(), "--target".to_owned(), linux.to_owned()]);
+
+        let overlay = Metadata::from_str("[package.metadata.docs.rs]\nno-deps = true").unwrap();
+        let merged = metadata.merge(overlay);
+        assert!(merged.document_examples);
+        assert!(merged.no_deps);
+    }
+
+    #[test]
+    fn test_exclude_deps() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            exclude-deps = [ "openssl-sys", "winapi" ]
+        "#;
+        let mut metadata = Metadata::from_str(manifest).unwrap();
+        let exclude_deps = vec!["openssl-sys".to_owned(), "winapi".to_owned()];
+        assert_eq!(metadata.exclude_deps, Some(exclude_deps.clone()));
+        assert_eq!(metadata.excluded_deps(), exclude_deps);
+
+        metadata.no_deps = true;
+        assert!(metadata.excluded_deps().is_empty());
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            all-feature = true
+            default-target = "x86_64-unknown-linux-gnu"
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.unknown_keys, vec!["all-feature".to_owned()]);
+        assert!(!metadata.all_features);
+    }
+
+    #[test]
+    fn test_diff() {
+        let base = r#"
+            [package.metadata.docs.rs]
+            features = [ "feature1" ]
+            default-target = "x86_64-unknown-linux-gnu"
+        "#;
+        let changed = r#"
+            [package.metadata.docs.rs]
+            features = [ "feature1" ]
+            all-features = true
+            default-target = "x86_64-apple-darwin"
+        "#;
+        let base = Metadata::from_str(base).unwrap();
+        let changed = Metadata::from_str(changed).unwrap();
+        let diff = base.diff(&changed);
+        assert_eq!(diff, vec![
+            FieldChange {
+                field: "all-features".to_owned(),
+                old: None,
+                new: Some("true".to_owned()),
+            },
+            FieldChange {
+                field: "default-target".to_owned(),
+                old: Some("\"x86_64-unknown-linux-gnu\"".to_owned()),
+                new: Some("\"x86_64-apple-darwin\"".to_owned()),
+            },
+        ]);
+        assert_eq!(diff[0].to_string(), "all-features: not set -> true");
+
+        assert!(base.diff(&base.clone()).is_empty());
+        assert_eq!(changed.diff(&base)[0].new, None);
+    }
+
+    #[test]
+    fn test_diff_lists() {
+        let base = r#"
+            [package.metadata.docs.rs]
+            features = [ "feature1", "feature2" ]
+            rustdoc-args = [ "--example-rustdoc-arg" ]
+        "#;
+        let changed = r#"
+            [package.metadata.docs.rs]
+            features = [ "feature1", "feature3" ]
+            no-deps = true
+        "#;
+        let base = Metadata::from_str(base).unwrap();
+        let changed = Metadata::from_str(changed).unwrap();
+        let diff: Vec<String> = base.diff(&changed).iter().map(|c| c.to_string()).collect();
+        assert_eq!(diff, vec![r#"features: ["feature1", "feature2"] -> ["feature1", "feature3"]"#,
+                              "no-deps: not set -> true",
+                              r#"rustdoc-args: ["--example-rustdoc-arg"] -> not set"#]);
+    }
+
+    #[test]
+    fn test_extra() {
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            all-features = true
+            experimental-search = "full"
+            experimental-languages = [ "en", "de" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.extra.keys().collect::<Vec<_>>(),
+                   vec!["experimental-languages", "experimental-search"]);
+        assert_eq!(metadata.extra["experimental-search"].as_str(), Some("full"));
+        assert_eq!(metadata.extra["experimental-languages"],
+                   toml::Value::Array(vec![toml::Value::String("en".to_owned()),
+                                           toml::Value::String("de".to_owned())]));
+
+        assert!(Metadata::default().extra.is_empty());
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\nall-features = true")
+            .unwrap();
+        assert!(metadata.extra.is_empty());
+    }
+
+    #[test]
+    fn test_targets() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            targets = [ "x86_64-unknown-linux-gnu", "wasm32-unknown-unknown" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(metadata.default_target.is_none());
+        let targets = metadata.targets.unwrap();
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[0], "x86_64-unknown-linux-gnu".to_owned());
+        assert_eq!(targets[1], "wasm32-unknown-unknown".to_owned());
+    }
+
+    #[test]
+    fn test_explicit_default_target() {
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            default-target = "x86_64-unknown-linux-gnu"
+        "#;
+        assert!(Metadata::from_str(manifest).unwrap().has_explicit_default_target());
+
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            targets = [ "x86_64-unknown-linux-gnu" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(!metadata.has_explicit_default_target());
+        assert!(!Metadata::default().has_explicit_default_target());
+    }
+
+    #[test]
+    fn test_targets_only_default_target() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            default-target = "x86_64-pc-windows-msvc"
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.default_target.unwrap(), "x86_64-pc-windows-msvc".to_owned());
+        assert!(metadata.targets.is_none());
+    }
+
+    #[test]
+    fn test_targets_with_default_target() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            default-target = "x86_64-pc-windows-msvc"
+            targets = [ "x86_64-unknown-linux-gnu", "x86_64-pc-windows-msvc" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.default_target.unwrap(), "x86_64-pc-windows-msvc".to_owned());
+        let targets = metadata.targets.unwrap();
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[0], "x86_64-pc-windows-msvc".to_owned());
+        assert_eq!(targets[1], "x86_64-unknown-linux-gnu".to_owned());
+    }
+
+    #[test]
+    fn test_targets_empty() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            targets = []
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.targets.unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_all_targets() {
+        let mut metadata = Metadata::default();
+        assert_eq!(metadata.all_targets(), vec!["x86_64-unknown-linux-gnu".to_owned()]);
+
+        metadata.targets = Some(Vec::new());
+        assert_eq!(metadata.all_targets(), vec!["x86_64-unknown-linux-gnu".to_owned()]);
+
+        metadata.default_target = Some("x86_64-pc-windows-msvc".to_owned());
+        assert_eq!(metadata.all_targets(), vec!["x86_64-pc-windows-msvc".to_owned()]);
+
+        metadata.targets = Some(vec!["x86_64-pc-windows-msvc".to_owned(),
+                                     "wasm32-unknown-unknown".to_owned()]);
+        assert_eq!(metadata.all_targets(), vec!["x86_64-pc-windows-msvc".to_owned(),
+                                                "wasm32-unknown-unknown".to_owned()]);
+    }
+
+    #[test]
+    fn test_effective_targets() {
+        let host = "i686-unknown-linux-gnu";
+        let mut metadata = Metadata::default();
+        assert_eq!(metadata.effective_targets(host), vec![host.to_owned()]);
+
+        metadata.default_target = Some("x86_64-pc-windows-msvc".to_owned());
+        assert_eq!(metadata.effective_targets(host), vec!["x86_64-pc-windows-msvc".to_owned()]);
+
+        metadata.default_target = None;
+        metadata.targets = Some(vec!["wasm32-unknown-unknown".to_owned(),
+                                     "x86_64-apple-darwin".to_owned()]);
+        assert_eq!(metadata.effective_targets(host), vec!["wasm32-unknown-unknown".to_owned(),
+                                                          "x86_64-apple-darwin".to_owned()]);
+
+        // default target comes first and is not repeated
+        metadata.default_target = Some("x86_64-apple-darwin".to_owned());
+        metadata.targets = Some(vec!["wasm32-unknown-unknown".to_owned(),
+                                     "x86_64-apple-darwin".to_owned(),
+                                     "wasm32-unknown-unknown".to_owned()]);
+        assert_eq!(metadata.effective_targets(host), vec!["x86_64-apple-darwin".to_owned(),
+                                                          "wasm32-unknown-unknown".to_owned()]);
+    }
+
+    #[test]
+    fn test_string_values() {
+        for key in &["features", "rustc-args", "rustdoc-args", "dependencies"] {
+            let manifest = format!("[package.metadata.docs.rs]\n{} = \"foo\"", key);
+            let metadata = Metadata::from_str(&manifest).unwrap();
+            assert_eq!(metadata.string_keys, vec![key.to_string()]);
+            assert_eq!(metadata.warnings(), vec![MetadataWarning::Deprecated {
+                key: format!("{} = \"...\"", key),
+                replacement: format!("{} = [ \"...\" ]", key),
+            }]);
+
+            let manifest = format!("[package.metadata.docs.rs]\n{} = [ \"foo\" ]", key);
+            let array = Metadata::from_str(&manifest).unwrap();
+            assert!(array.string_keys.is_empty());
+            assert!(array.warnings().is_empty());
+
+            for metadata in &[metadata, array] {
+                let value = match *key {
+                    "features" => metadata.features.clone(),
+                    "rustc-args" => metadata.rustc_args.clone(),
+                    "rustdoc-args" => metadata.rustdoc_args.clone(),
+                    _ => metadata.dependencies.clone(),
+                };
+                assert_eq!(value, Some(vec!["foo".to_owned()]));
+            }
+
+            let manifest = format!("[package.metadata.docs.rs]\n{} = 1", key);
+            assert!(Metadata::from_str(&manifest).is_err());
+        }
+    }
+
+    #[test]
+    fn test_system_packages_for() {
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            dependencies = [ "libssl-dev" ]
+
+            [package.metadata.docs.rs.dependencies-map]
+            apk = [ "openssl-dev", "pkgconf" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(metadata.unknown_keys.is_empty());
+        assert_eq!(metadata.system_packages_for("apk"),
+                   vec!["openssl-dev".to_owned(), "pkgconf".to_owned()]);
+        assert_eq!(metadata.system_packages_for("apt"), vec!["libssl-dev".to_owned()]);
+
+        let manifest = "[package.metadata.docs.rs]\ndependencies = [ \"libssl-dev\" ]";
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.system_packages_for("apt"), vec!["libssl-dev".to_owned()]);
+
+        assert!(Metadata::default().system_packages_for("apt").is_empty());
+    }
+
+    #[test]
+    fn test_dependency_specs() {
+        let metadata = Metadata::from_str(
+            "[package.metadata.docs.rs]\ndependencies = [ \"libfoo-dev\" ]").unwrap();
+        assert_eq!(metadata.dependencies, Some(vec!["libfoo-dev".to_owned()]));
+        let specs = metadata.dependency_specs.unwrap();
+        assert_eq!(specs.len(), 1);
+        assert_eq!(specs["libfoo-dev"], "*");
+
+        let manifest = r#"
+            [package.metadata.docs.rs.dependencies]
+            libssl-dev = "1.1.*"
+            libfoo-dev = true
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.dependencies,
+                   Some(vec!["libfoo-dev".to_owned(), "libssl-dev".to_owned()]));
+        let specs = metadata.dependency_specs.as_ref().unwrap();
+        assert_eq!(specs.len(), 2);
+        assert_eq!(specs["libssl-dev"], "1.1.*");
+        assert_eq!(specs["libfoo-dev"], "*");
+
+        let parsed = Metadata::from_str(&metadata.to_toml().to_string()).unwrap();
+        assert_eq!(parsed.dependency_specs, metadata.dependency_specs);
+
+        let manifest = "[package.metadata.docs.rs.dependencies]\nlibfoo-dev = false";
+        assert!(Metadata::from_str(manifest).is_err());
+    }
+
+    #[test]
+    fn test_readme() {
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\nreadme = \"docs/README.md\"")
+            .unwrap();
+        assert_eq!(metadata.readme, Some("docs/README.md".to_owned()));
+        assert!(metadata.validate().is_ok());
+
+        let mut metadata = Metadata::default();
+        metadata.readme = Some("/etc/passwd".to_owned());
+        assert!(metadata.validate().is_err());
+
+        metadata.readme = Some("docs/../../README.md".to_owned());
+        assert!(metadata.validate().is_err());
+    }
+
+    #[test]
+    fn test_classify_feature() {
+        assert_eq!(classify_feature("serde"), FeatureRef::Feature("serde".to_owned()));
+        assert_eq!(classify_feature("dep:serde"), FeatureRef::Dependency("serde".to_owned()));
+        assert_eq!(classify_feature("serde/derive"), FeatureRef::DependencyFeature {
+            dependency: "serde".to_owned(),
+            feature: "derive".to_owned(),
+            weak: false,
+        });
+        assert_eq!(classify_feature("serde?/derive"), FeatureRef::DependencyFeature {
+            dependency: "serde".to_owned(),
+            feature: "derive".to_owned(),
+            weak: true,
+        });
+    }
+
+    #[test]
+    fn test_unknown_features() {
+        let metadata = MetadataBuilder::new()
+            .features(&["std", "dep:serde", "serde/derive", "tokio?/rt", "unknown"])
+            .build();
+        let declared = vec!["std".to_owned(), "serde".to_owned()];
+        assert_eq!(metadata.unknown_features(&declared), vec!["unknown".to_owned()]);
+        assert!(Metadata::default().unknown_features(&declared).is_empty());
+    }
+
+    #[test]
+    fn test_expand_features() {
+        let all_features: Vec<String> = ["std", "backend-gl", "backend-vulkan", "serde"].iter()
+            .map(|f| f.to_string())
+            .collect();
+
+        let metadata = MetadataBuilder::new().features(&["backend-*", "serde"]).build();
+        assert_eq!(metadata.expand_features(&all_features),
+                   vec!["backend-gl".to_owned(), "backend-vulkan".to_owned(),
+                        "serde".to_owned()]);
+        assert!(metadata.features_warnings(&all_features).is_empty());
+
+        let metadata = MetadataBuilder::new().features(&["std", "*", "dep:log"]).build();
+        assert_eq!(metadata.expand_features(&all_features),
+                   vec!["std".to_owned(), "backend-gl".to_owned(), "backend-vulkan".to_owned(),
+                        "serde".to_owned(), "dep:log".to_owned()]);
+
+        let metadata = MetadataBuilder::new().features(&["std", "frontend-*"]).build();
+        assert_eq!(metadata.expand_features(&all_features), vec!["std".to_owned()]);
+        let warnings = metadata.features_warnings(&all_features);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].to_string().contains("'frontend-*'"), "{}", warnings[0]);
+
+        assert!(Metadata::default().expand_features(&all_features).is_empty());
+    }
+
+    #[test]
+    fn test_documentation_url() {
+        let metadata = Metadata::from_str(r#"
+            [package]
+            name = "test"
+            documentation = "https://docs.rs/test"
+
+            [package.metadata.docs.rs]
+            documentation = "https://example.com/docs"
+        "#).unwrap();
+        assert_eq!(metadata.documentation_url, Some("https://example.com/docs".to_owned()));
+        assert!(metadata.validate().is_ok());
+
+        let metadata = Metadata::from_str("[package]\ndocumentation = \"https://docs.rs/test\"")
+            .unwrap();
+        assert!(metadata.documentation_url.is_none());
+
+        let mut metadata = Metadata::default();
+        metadata.documentation_url = Some("not a url".to_owned());
+        assert!(metadata.validate().is_err());
+        metadata.documentation_url = Some("ftp://example.com/docs".to_owned());
+        assert!(metadata.validate().is_err());
+    }
+
+    #[test]
+    fn test_resolve_rustdoc_paths() {
+        use std::fs::{self, File};
+
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            rustdoc-args = [
+                "--html-in-header", "docs/header.html",
+                "--html-before-content=docs/before.html",
+                "--html-after-content", "./docs/after.html",
+                "--markdown-css=docs/style.css",
+                "--cfg", "docs/header.html",
+            ]
+
+            [package.metadata.docs.rs.'wasm32-unknown-unknown']
+            rustdoc-args = [ "--html-in-header", "docs/wasm.html" ]
+
+            [package.metadata.docs.rs.nightly]
+            rustdoc-args = [ "--markdown-css", "docs/nightly.css" ]
+        "#;
+        let dir = tempdir::TempDir::new("cratesfyi-rustdoc-paths").unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let root = root.as_path();
+        fs::create_dir(root.join("docs")).unwrap();
+        for file in &["header.html", "before.html", "after.html", "style.css", "wasm.html",
+                      "nightly.css"] {
+            File::create(root.join("docs").join(file)).unwrap();
+        }
+        let path = |p: &str| root.join(p).to_string_lossy().into_owned();
+        let mut metadata = Metadata::from_str(manifest).unwrap();
+        metadata.resolve_rustdoc_paths(root).unwrap();
+        assert_eq!(metadata.rustdoc_args.unwrap(),
+                   vec!["--html-in-header".to_owned(), path("docs/header.html"),
+                        format!("--html-before-content={}", path("docs/before.html")),
+                        "--html-after-content".to_owned(), path("docs/after.html"),
+                        format!("--markdown-css={}", path("docs/style.css")),
+                        "--cfg".to_owned(), "docs/header.html".to_owned()]);
+        assert_eq!(metadata.per_target_rustdoc_args["wasm32-unknown-unknown"],
+                   vec!["--html-in-header".to_owned(), path("docs/wasm.html")]);
+        assert_eq!(metadata.channel_args["nightly"].rustdoc_args.clone().unwrap(),
+                   vec!["--markdown-css".to_owned(), path("docs/nightly.css")]);
+
+        let mut metadata = MetadataBuilder::new()
+            .rustdoc_args(&["--html-in-header", "../../etc/passwd"])
+            .build();
+        let err = metadata.resolve_rustdoc_paths(root).unwrap_err();
+        assert_eq!(err.to_string(),
+                   "--html-in-header `../../etc/passwd` must be a path inside the crate");
+
+        let mut metadata = MetadataBuilder::new()
+            .rustdoc_args(&["--markdown-css=/etc/passwd"])
+            .build();
+        assert!(metadata.resolve_rustdoc_paths(root).is_err());
+
+        let mut metadata = MetadataBuilder::new()
+            .rustdoc_args(&["--html-in-header", "docs"])
+            .build();
+        let err = metadata.resolve_rustdoc_paths(root).unwrap_err();
+        assert_eq!(err.to_string(), "--html-in-header `docs` is not a file");
+    }
+
+    #[test]
+    fn test_extra_files() {
+        use std::fs::{self, File};
+        use std::os::unix::fs::symlink;
+
+        let metadata = Metadata::from_str(r#"
+            [package.metadata.docs.rs]
+            extra-files = [ "docs/diagram.svg" ]
+        "#).unwrap();
+        assert_eq!(metadata.extra_files, Some(vec!["docs/diagram.svg".to_owned()]));
+        assert!(metadata.validate().is_ok());
+
+        let dir = tempdir::TempDir::new("cratesfyi-extra-files").unwrap();
+        assert!(metadata.extra_files_paths(dir.path()).is_err());
+        fs::create_dir(dir.path().join("docs")).unwrap();
+        File::create(dir.path().join("docs/diagram.svg")).unwrap();
+        assert_eq!(metadata.extra_files_paths(dir.path()).unwrap(),
+                   vec![dir.path().canonicalize().unwrap().join("docs/diagram.svg")]);
+
+        let mut metadata = Metadata::default();
+        assert!(metadata.extra_files_paths(dir.path()).unwrap().is_empty());
+        metadata.extra_files = Some(vec!["../escape".to_owned()]);
+        assert!(metadata.validate().is_err());
+        assert!(metadata.extra_files_paths(dir.path()).is_err());
+
+        for path in &["", ".", "docs"] {
+            metadata.extra_files = Some(vec![path.to_string()]);
+            assert!(metadata.extra_files_paths(dir.path()).is_err(), "{:?} allowed", path);
+        }
+        metadata.extra_files = Some(vec!["".to_owned()]);
+        assert!(metadata.validate().is_err());
+
+        symlink("/etc/passwd", dir.path().join("docs/passwd")).unwrap();
+        metadata.extra_files = Some(vec!["docs/passwd".to_owned()]);
+        assert!(metadata.validate().is_ok());
+        assert_eq!(metadata.extra_files_paths(dir.path()).unwrap_err().to_string(),
+                   "extra-files `docs/passwd` must be a path inside the crate");
+    }
+
+    #[test]
+    fn test_default_features() {
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\ndefault-features = false")
+            .unwrap();
+        assert!(metadata.no_default_features);
+        assert!(metadata.validate().is_ok());
+
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\ndefault-features = true")
+            .unwrap();
+        assert!(!metadata.no_default_features);
+        assert!(metadata.validate().is_ok());
+
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\nno-default-features = true")
+            .unwrap();
+        assert!(metadata.no_default_features);
+        assert!(metadata.default_features.is_none());
+        assert!(metadata.validate().is_ok());
+
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            default-features = true
+            no-default-features = true
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(metadata.validate().is_err());
+    }
+
+    #[test]
+    fn test_from_str_with_warnings() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            all-feature = true
+            rust-docs-args = [ "--cfg", "docsrs" ]
+            no-default-features = true
+        "#;
+        let (metadata, warnings) = Metadata::from_str_with_warnings(manifest).unwrap();
+        assert!(metadata.no_default_features);
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].to_string(),
+                   "unknown key 'all-feature'; did you mean 'all-features'?");
+        assert_eq!(warnings[1].to_string(),
+                   "unknown key 'rust-docs-args'; did you mean 'rustdoc-args'?");
+    }
+
+    #[test]
+    fn test_known_keys() {
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            features = [ "feature1" ]
+            feature-matrix = [ [ "feature1" ] ]
+            all-features = false
+            features-doc = "doc-all"
+            no-default-features = true
+            default-features = false
+            no-deps = false
+            exclude-deps = [ "winapi" ]
+            default-target = "x86_64-unknown-linux-gnu"
+            targets = [ "x86_64-unknown-linux-gnu" ]
+            exclude-targets = [ "x86_64-apple-darwin" ]
+            cargo-args = [ "-Zbuild-std" ]
+            rustc-args = [ "--example-rustc-arg" ]
+            rustdoc-args = [ "--example-rustdoc-arg" ]
+            cfg = [ "example_cfg" ]
+            rustdoc-header = "docs/header.html"
+            docsrs-cfg = true
+            document-private-items = false
+            document-examples = false
+            documentation = "https://example.com/docs"
+            readme = "docs/README.md"
+            extra-files = [ "docs/diagram.svg" ]
+            dependencies = [ "example-system-dependency" ]
+            toolchain = "nightly"
+            rust-version = "1.31"
+            build = true
+            build-timeout = 600
+            max-output-size = "500MB"
+            priority = 0
+            memory-limit = 4096
+            cpu-quota = 2
+
+            [package.metadata.docs.rs.rustdoc-map]
+            example-dependency = "https://example.com/docs/"
+
+            [package.metadata.docs.rs.dependencies-map]
+            apt = [ "example-system-dependency" ]
+
+            [package.metadata.docs.rs.env]
+            EXAMPLE_ENV_VAR = "example-value"
+
+            [package.metadata.docs.rs.stable]
+            [package.metadata.docs.rs.beta]
+            [package.metadata.docs.rs.nightly]
+        "#;
+        let table: toml::Value = toml::from_str(manifest).unwrap();
+        let table = table["package"]["metadata"]["docs"]["rs"].as_table().unwrap();
+        assert!(Metadata::unknown_keys(table).is_empty());
+        for key in Metadata::known_keys() {
+            assert!(table.contains_key(*key), "{} is missing from the manifest", key);
+        }
+
+        // every key read by the parser must be known
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(metadata.unknown_keys.is_empty(), "{:?}", metadata.unknown_keys);
+        assert!(metadata.warnings().is_empty(), "{:?}", metadata.warnings());
+        let value = metadata.to_toml();
+        let table = value["package"]["metadata"]["docs"]["rs"].as_table().unwrap();
+        assert!(Metadata::unknown_keys(table).is_empty());
+    }
+
+    #[test]
+    fn test_suggest_key() {
+        assert_eq!(suggest_key("rustdocs-args"), Some("rustdoc-args"));
+        assert_eq!(suggest_key("rustc-arg"), Some("rustc-args"));
+        assert_eq!(suggest_key("all-feature"), Some("all-features"));
+        assert_eq!(suggest_key("no-default-fatures"), Some("no-default-features"));
+        assert_eq!(suggest_key("default_target"), Some("default-target"));
+        assert_eq!(suggest_key("dependencie"), Some("dependencies"));
+        assert_eq!(suggest_key("dependency"), None);
+        assert_eq!(suggest_key("something-else"), None);
+    }
+
+    #[test]
+    fn test_key_suggestions() {
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            rustdocs-args = [ "--cfg", "docsrs" ]
+            something-else = true
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.key_suggestions(),
+                   vec![("rustdocs-args".to_owned(), "rustdoc-args".to_owned())]);
+        assert!(Metadata::default().key_suggestions().is_empty());
+    }
+
+    #[test]
+    fn test_unknown_targets() {
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            default-target = "x86_64-unknown-linex-gnu"
+            targets = [ "x86_64-unknown-linex-gnu", "x86_64-apple-darwin" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(metadata.validate().is_ok());
+        let warnings: Vec<String> = metadata.warnings().iter().map(|w| w.to_string()).collect();
+        assert_eq!(warnings,
+                   vec!["unknown target 'x86_64-unknown-linex-gnu' in default-target; \
+                         did you mean 'x86_64-unknown-linux-gnu'?".to_owned(),
+                        "unknown target 'x86_64-unknown-linex-gnu' in targets; \
+                         did you mean 'x86_64-unknown-linux-gnu'?".to_owned()]);
+
+        // a target which is newer than KNOWN_TARGETS is only a warning
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            targets = [ "aarch64-apple-darwin", "wasm32-unknown-unknown" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(metadata.validate().is_ok());
+        assert!(metadata.validate_targets().is_ok());
+        assert_eq!(metadata.warnings().len(), 1);
+
+        let manifest = "[package.metadata.docs.rs]\ntargets = \"*\"";
+        assert!(Metadata::from_str(manifest).unwrap().warnings().is_empty());
+    }
+
+    #[test]
+    fn test_sanitize_args() {
+        let mut metadata = Metadata::default();
+        assert!(metadata.sanitize_args().is_ok());
+
+        metadata.rustdoc_args = Some(strings(&["--cfg", "foo", "-L", "target/deps"]));
+        metadata.rustc_args = Some(strings(&["-Ldependency=target/deps", "-C", "opt-level=2"]));
+        assert!(metadata.sanitize_args().is_ok());
+
+        let denied = [&["--out-dir", "/tmp"][..], &["--out-dir=/tmp"], &["-o", "out"],
+                      &["-o/tmp"], &["--output", "/tmp"], &["--output=/tmp"],
+                      &["--out-dir /tmp"], &["--emit=asm"], &["-L", "/etc"],
+                      &["-Lnative=/usr/lib"]];
+        for args in denied.iter() {
+            let mut metadata = Metadata::default();
+            metadata.rustc_args = Some(strings(args));
+            match metadata.sanitize_args() {
+                Ok(_) => panic!("{:?} allowed", args),
+                Err(e) => {
+                    let flag = args[0].split_whitespace().next().unwrap();
+                    assert!(e.to_string().contains(flag), "{}", e);
+                }
+            }
+        }
+
+        let mut metadata = Metadata::default();
+        metadata.cargo_args = Some(strings(&["--target-dir", "/tmp"]));
+        match metadata.sanitize_args() {
+            Ok(_) => panic!("--target-dir allowed"),
+            Err(e) => assert_eq!(e.to_string(), "`--target-dir` is not allowed in cargo-args"),
+        }
+
+        let tables = [("[package.metadata.docs.rs.targets.x86_64-pc-windows-msvc]\n\
+                        rustc-args = [ \"--out-dir=/tmp\" ]",
+                       "targets.'x86_64-pc-windows-msvc'.rustc-args"),
+                      ("[package.metadata.docs.rs.targets.'cfg(windows)']\n\
+                        rustdoc-args = [ \"--output\", \"/tmp\" ]",
+                       "targets.'cfg(windows)'.rustdoc-args"),
+                      ("[package.metadata.docs.rs.'wasm32-unknown-unknown']\n\
+                        rustdoc-args = [ \"-o/tmp\" ]",
+                       "'wasm32-unknown-unknown'.rustdoc-args"),
+                      ("[package.metadata.docs.rs.nightly]\n\
+                        rustc-args = [ \"--emit\", \"asm\" ]",
+                       "nightly.rustc-args"),
+                      ("[package.metadata.docs.rs.env]\n\
+                        EXTRA_FLAGS = \"--cfg foo --out-dir /tmp\"",
+                       "env.EXTRA_FLAGS")];
+        for &(manifest, key) in tables.iter() {
+            let metadata = Metadata::from_str(manifest).unwrap();
+            match metadata.sanitize_args() {
+                Ok(_) => panic!("{} allowed", key),
+                Err(e) => assert!(e.to_string().ends_with(&format!("is not allowed in {}", key)),
+                                  "{}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate_targets() {
+        let mut metadata = Metadata::default();
+        assert!(metadata.validate_targets().is_ok());
+
+        metadata.default_target = Some("x86_64-unknown-linux-gnu".to_owned());
+        assert!(metadata.validate_targets().is_ok());
+
+        metadata.targets = Some(vec!["x86_64-apple-darwin".to_owned(), "linux".to_owned()]);
+        let err = metadata.validate_targets().unwrap_err().to_string();
+        assert!(err.contains("`linux`"), "{}", err);
+
+        metadata.targets = None;
+        metadata.default_target = Some("x86_64-linux".to_owned());
+        assert!(metadata.validate_targets().is_err());
+    }
+
+    #[test]
+    fn test_exclude_targets() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            targets = "*"
+            exclude-targets = [ "x86_64-apple-darwin", "thumbv6m-none-eabi" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        let supported = vec!["x86_64-unknown-linux-gnu".to_owned(),
+                             "x86_64-pc-windows-msvc".to_owned(),
+                             "x86_64-apple-darwin".to_owned()];
+        assert_eq!(metadata.resolved_targets(&supported),
+                   vec!["x86_64-unknown-linux-gnu".to_owned(),
+                        "x86_64-pc-windows-msvc".to_owned()]);
+        assert_eq!(metadata.exclude_targets_warnings(&supported),
+                   vec![MetadataWarning::Invalid {
+                       key: "exclude-targets".to_owned(),
+                       reason: "exclude-targets entry 'thumbv6m-none-eabi' is not a supported \
+                                target, ignoring it".to_owned(),
+                   }]);
+        assert!(Metadata::default().exclude_targets_warnings(&supported).is_empty());
+
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            default-target = "x86_64-apple-darwin"
+            targets = "*"
+            exclude-targets = [ "x86_64-apple-darwin" ]
+        "#;
+        let err = Metadata::from_str(manifest).unwrap().validate().unwrap_err();
+        assert_eq!(err.to_string(),
+                   "default-target `x86_64-apple-darwin` can't be in exclude-targets");
+    }
+
+    #[test]
+    fn test_all_targets_wildcard() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            default-target = "x86_64-pc-windows-msvc"
+            targets = "*"
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(metadata.has_all_targets());
+        assert!(metadata.validate().is_ok());
+        assert!(metadata.validate_targets().is_ok());
+
+        let supported = vec!["x86_64-unknown-linux-gnu".to_owned(),
+                             "x86_64-pc-windows-msvc".to_owned(),
+                             "x86_64-apple-darwin".to_owned()];
+        assert_eq!(metadata.resolved_targets(&supported),
+                   vec!["x86_64-pc-windows-msvc".to_owned(),
+                        "x86_64-unknown-linux-gnu".to_owned(),
+                        "x86_64-apple-darwin".to_owned()]);
+
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            targets = [ "*", "x86_64-apple-darwin" ]
+        "#;
+        assert!(Metadata::from_str(manifest).is_err());
+
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            targets = "x86_64-apple-darwin"
+        "#;
+        assert!(Metadata::from_str(manifest).is_err());
+    }
+
+    #[test]
+    fn test_from_source_dir() {
+        use std::fs::File;
+        use std::io::Write;
+
+        let without_metadata = r#"
+            [package]
+            name = "test"
+        "#;
+        let with_metadata = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            all-features = true
+        "#;
+
+        let dir = tempdir::TempDir::new("cratesfyi").unwrap();
+        assert!(Metadata::from_source_dir(dir.path(), None).is_err());
+
+        File::create(dir.path().join("Cargo.toml.orig")).unwrap()
+            .write_all(without_metadata.as_bytes()).unwrap();
+        assert!(!Metadata::from_source_dir(dir.path(), None).unwrap().all_features);
+
+        File::create(dir.path().join("Cargo.toml")).unwrap()
+            .write_all(with_metadata.as_bytes()).unwrap();
+        assert!(Metadata::from_source_dir(dir.path(), None).unwrap().all_features);
+
+        File::create(dir.path().join("Cargo.toml.orig")).unwrap()
+            .write_all(with_metadata.replace("true", "false").as_bytes()).unwrap();
+        assert!(!Metadata::from_source_dir(dir.path(), None).unwrap().all_features);
+
+        File::create(dir.path().join("Cargo.toml")).unwrap()
+            .write_all(without_metadata.as_bytes()).unwrap();
+        File::create(dir.path().join("Cargo.toml.orig")).unwrap()
+            .write_all(with_metadata.as_bytes()).unwrap();
+        assert!(Metadata::from_source_dir(dir.path(), None).unwrap().all_features);
+
+        // invalid metadata is parsed, it's rejected by validate
+        File::create(dir.path().join("Cargo.toml.orig")).unwrap()
+            .write_all(with_metadata.replace("all-features = true",
+                                             "targets = [ \"wasm32-unknown-unknown\", \
+                                                         \"wasm32-unknown-unknown\" ]")
+                       .as_bytes()).unwrap();
+        let metadata = Metadata::from_source_dir(dir.path(), None).unwrap();
+        assert_eq!(metadata.targets.as_ref().map(|t| t.len()), Some(2));
+        assert!(metadata.validate().is_err());
+    }
+
+    #[test]
+    fn test_metadata_builder() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            features = [ "feature1", "feature2" ]
+            no-default-features = true
+            default-target = "x86_64-apple-darwin"
+            targets = [ "x86_64-apple-darwin", "x86_64-pc-windows-msvc" ]
+            rustdoc-args = [ "--example-rustdoc-arg" ]
+            toolchain = "nightly"
+        "#;
+        let expected = Metadata::from_str(manifest).unwrap();
+        let metadata = MetadataBuilder::new()
+            .features(&["feature1", "feature2"])
+            .no_default_features(true)
+            .default_target("x86_64-apple-darwin")
+            .targets(&["x86_64-apple-darwin", "x86_64-pc-windows-msvc"])
+            .rustdoc_args(&["--example-rustdoc-arg"])
+            .toolchain("nightly")
+            .build();
+        assert_eq!(metadata.to_toml(), expected.to_toml());
+        assert!(metadata.has_explicit_default_target());
+        assert!(metadata.validate().is_ok());
+
+        let metadata = MetadataBuilder::new().all_features(true).skip_build(true).build();
+        assert!(metadata.all_features);
+        assert!(!metadata.build);
+        assert!(metadata.features.is_none());
+        assert_eq!(MetadataBuilder::new().build().to_toml(), Metadata::default().to_toml());
+    }
+
+    #[test]
+    fn test_merge() {
+        let base = r#"
+            [package.metadata.docs.rs]
+            all-features = true
+            no-deps = true
+            default-target = "x86_64-unknown-linux-gnu"
+            rustdoc-args = [ "--cfg", "base" ]
+            targets = [ "x86_64-unknown-linux-gnu", "x86_64-apple-darwin" ]
+        "#;
+        let overlay = r#"
+            [package.metadata.docs.rs]
+            no-deps = false
+            default-target = "x86_64-apple-darwin"
+            rustdoc-args = [ "--cfg", "overlay" ]
+        "#;
+        let base = Metadata::from_str(base).unwrap();
+        let overlay = Metadata::from_str(overlay).unwrap();
+        let merged = base.clone().merge(overlay);
+
+        // overlay overrides
+        assert!(!merged.no_deps);
+        assert_eq!(merged.default_target, Some("x86_64-apple-darwin".to_owned()));
+
+        // overlay leaves base alone
+        assert!(merged.all_features);
+        assert_eq!(merged.targets, base.targets);
+
+        // lists are replaced rather than concatenated
+        assert_eq!(merged.rustdoc_args, Some(vec!["--cfg".to_owned(), "overlay".to_owned()]));
+
+        assert!(merged.is_set("no-deps"));
+        assert!(merged.validate().is_ok());
+        assert_eq!(base.clone().merge(Metadata::default()), base);
+        assert_eq!(Metadata::default().merge(base.clone()), base);
+
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            targets = [ "x86_64-unknown-linux-gnu" ]
+
+            [package.metadata.docs.rs.x86_64-pc-windows-msvc]
+            features = [ "windows" ]
+
+            [package.metadata.docs.rs.'cfg(unix)']
+            rustdoc-args = [ "--cfg", "unix" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.clone().merge(Metadata::default()), metadata);
+        assert_eq!(Metadata::default().merge(metadata.clone()), metadata);
+
+        // overrides are kept when the overlay only lists its targets
+        let overlay = r#"
+            [package.metadata.docs.rs]
+            targets = [ "x86_64-apple-darwin" ]
+        "#;
+        let overlay = Metadata::from_str(overlay).unwrap();
+        let merged = metadata.clone().merge(overlay);
+        assert_eq!(merged.target_overrides, metadata.target_overrides);
+        assert_eq!(merged.cfg_target_overrides, metadata.cfg_target_overrides);
+    }
+
+    #[test]
+    fn test_to_toml() {
+        use std::time::Duration;
+
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            features = [ "feature1", "feature2" ]
+            all-features = true
+            no-default-features = true
+            default-target = "x86_64-unknown-linux-gnu"
+            cargo-args = [ "-Zbuild-std" ]
+            rustc-args = [ "--example-rustc-arg" ]
+            rustdoc-args = [ "--example-rustdoc-arg" ]
+            dependencies = [ "example-system-dependency" ]
+            build-timeout = "15min"
+
+            [package.metadata.docs.rs.env]
+            EXAMPLE_ENV_VAR = "example-value"
+
+            [package.metadata.docs.rs.targets.x86_64-unknown-linux-gnu]
+
+            [package.metadata.docs.rs.targets.wasm32-unknown-unknown]
+            features = [ "wasm" ]
+
+            [package.metadata.docs.rs.'wasm32-unknown-unknown']
+            rustdoc-args = [ "--html-in-header", "wasm.html" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        let value = metadata.to_toml();
+        let table = value["package"]["metadata"]["docs"]["rs"].as_table().unwrap();
+        assert_eq!(table["default-target"].as_str(), Some("x86_64-unknown-linux-gnu"));
+        assert_eq!(table["all-features"].as_bool(), Some(true));
+        assert_eq!(table["build-timeout"].as_integer(), Some(15 * 60));
+
+        assert_eq!(Metadata::from_str(&value.to_string()).unwrap(), metadata);
+
+        // overrides of targets which are not listed are written in top-level target tables
+        for targets in &["", "targets = [ \"wasm32-unknown-unknown\" ]"] {
+            let manifest = format!(r#"
+                [package.metadata.docs.rs]
+                {}
+
+                [package.metadata.docs.rs.x86_64-pc-windows-msvc]
+                features = [ "winapi" ]
+                rustdoc-args = [ "--cfg", "windows_docs" ]
+
+                [package.metadata.docs.rs.'cfg(unix)']
+                rustc-args = [ "--cfg", "unix_docs" ]
+            "#, targets);
+            let metadata = Metadata::from_str(&manifest).unwrap();
+            assert_eq!(metadata.target_overrides.len(), 1);
+            assert_eq!(metadata.cfg_target_overrides.len(), 1);
+            let value = metadata.to_toml();
+            assert_eq!(Metadata::from_str(&value.to_string()).unwrap(), metadata);
+        }
+
+        let metadata = Metadata::default();
+        let value = metadata.to_toml();
+        assert!(value["package"]["metadata"]["docs"]["rs"].as_table().unwrap().is_empty());
+        assert_eq!(Metadata::from_str(&value.to_string()).unwrap().to_toml(), value);
+
+        let mut metadata = Metadata::default();
+        metadata.build_timeout = Some(Duration::from_secs(u64::max_value()));
+        metadata.max_output_size = Some(u64::max_value());
+        metadata.memory_limit_mb = Some(u64::max_value());
+        let value = metadata.to_toml();
+        let table = value["package"]["metadata"]["docs"]["rs"].as_table().unwrap();
+        for key in &["build-timeout", "max-output-size", "memory-limit"] {
+            assert_eq!(table[*key].as_integer(), Some(i64::max_value()), "{}", key);
+        }
+        let parsed = Metadata::from_str(&value.to_string()).unwrap();
+        assert_eq!(parsed.max_output_size, Some(i64::max_value() as u64));
+        assert_eq!(parsed.to_toml(), value);
+    }
+
+    #[test]
+    fn test_summary() {
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            features = [ "feature1", "feature2" ]
+            no-default-features = true
+            default-target = "x86_64-unknown-linux-gnu"
+            targets = [ "wasm32-unknown-unknown" ]
+            rustc-args = [ "--example-rustc-arg" ]
+            rustdoc-args = [ "--cfg", "example" ]
+            toolchain = "nightly"
+            build-timeout = 600
+            priority = 5
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.summary(), "\
+targets: x86_64-unknown-linux-gnu, wasm32-unknown-unknown
+features: feature1, feature2
+no-default-features: true
+rustc-args: 1
+rustdoc-args: 2
+toolchain: nightly
+build-timeout: 600s
+priority: 5");
+
+        assert_eq!(Metadata::default().summary(), "");
+        let metadata = MetadataBuilder::new().all_features(true).skip_build(true).build();
+        assert_eq!(metadata.summary(), "all-features: true\nbuild: false");
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            features = [ "feature1", "feature2" ]
+            default-target = "x86_64-unknown-linux-gnu"
+            rustdoc-args = [ "--example-rustdoc-arg" ]
+
+            [package.metadata.docs.rs.env]
+            A = "1"
+            B = "2"
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert_eq!(metadata.content_hash(), Metadata::from_str(manifest).unwrap().content_hash());
+        assert_eq!(metadata.content_hash(), metadata.clone().content_hash());
+
+        // key order in manifest does not matter
+        let reordered = Metadata::from_str(r#"
+            [package.metadata.docs.rs]
+            rustdoc-args = [ "--example-rustdoc-arg" ]
+            default-target = "x86_64-unknown-linux-gnu"
+            features = [ "feature1", "feature2" ]
+
+            [package.metadata.docs.rs.env]
+            B = "2"
+            A = "1"
+        "#).unwrap();
+        assert_eq!(metadata.content_hash(), reordered.content_hash());
+
+        let mut changed = metadata.clone();
+        changed.no_deps = true;
+        assert!(metadata.content_hash() != changed.content_hash());
+
+        let mut changed = metadata.clone();
+        changed.rustdoc_args = Some(vec!["--other-rustdoc-arg".to_owned()]);
+        assert!(metadata.content_hash() != changed.content_hash());
+
+        assert_eq!(Metadata::default().content_hash(), Metadata::default().content_hash());
+        assert!(Metadata::default().content_hash() != metadata.content_hash());
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let metadata = MetadataBuilder::new()
+            .features(&["feature1", "feature2"])
+            .rustc_args(&["--cfg", "foo"])
+            .dependencies(&["libssl-dev", "libgit2-dev"])
+            .build();
+        let key = metadata.cache_key();
+        assert_eq!(key.len(), 16);
+        assert_eq!(key, metadata.clone().cache_key());
+
+        let reordered = MetadataBuilder::new()
+            .features(&["feature2", "feature1"])
+            .rustc_args(&["--cfg", "foo"])
+            .dependencies(&["libgit2-dev", "libssl-dev"])
+            .build();
+        assert_eq!(key, reordered.cache_key());
+        assert!(metadata.content_hash() != reordered.content_hash());
+
+        let changed = MetadataBuilder::new()
+            .features(&["feature1", "feature3"])
+            .rustc_args(&["--cfg", "foo"])
+            .dependencies(&["libssl-dev", "libgit2-dev"])
+            .build();
+        assert!(key != changed.cache_key());
+
+        let changed = MetadataBuilder::new()
+            .features(&["feature1", "feature2"])
+            .rustc_args(&["foo", "--cfg"])
+            .dependencies(&["libssl-dev", "libgit2-dev"])
+            .build();
+        assert!(key != changed.cache_key());
+    }
+
+    #[test]
+    fn test_serialize() {
+        use serde_json;
+
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            features = [ "feature1", "feature2" ]
+            no-default-features = true
+            default-target = "x86_64-unknown-linux-gnu"
+            cargo-args = [ "-Zbuild-std" ]
+            rustdoc-args = [ "--example-rustdoc-arg" ]
+            cfg = [ "example_cfg" ]
+            rustdoc-header = "docs/header.html"
+            toolchain = "nightly"
+            build-timeout = 600
+            priority = 10
+
+            [package.metadata.docs.rs.env]
+            EXAMPLE_ENV_VAR = "example-value"
+
+            [package.metadata.docs.rs.dependencies]
+            libssl-dev = "1.1.*"
+
+            [package.metadata.docs.rs.targets.x86_64-unknown-linux-gnu]
+
+            [package.metadata.docs.rs.targets.wasm32-unknown-unknown]
+            features = [ "wasm" ]
+
+            [package.metadata.docs.rs.'wasm32-unknown-unknown']
+            rustdoc-args = [ "--html-in-header", "wasm.html" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        let json = serde_json::to_string(&metadata).unwrap();
+        assert!(!json.contains("null"), "{}", json);
+        assert!(json.contains("\"no-default-features\":true"), "{}", json);
+
+        let parsed = serde_json::from_str::<Metadata>(&json).unwrap();
+        assert_eq!(parsed, metadata);
+
+        let json = serde_json::to_string(&Metadata::default()).unwrap();
+        assert_eq!(json, "{}");
+
+        assert!(serde_json::from_str::<Metadata>(r#"{ "all-features": 1 }"#).is_err());
+    }
+
+    #[test]
+    fn test_package_source_dir() {
+        use std::fs::File;
+        use std::path::Path;
+        use super::{package_source_dir, MetadataError};
+
+        let dir = tempdir::TempDir::new("cratesfyi").unwrap();
+        assert_eq!(package_source_dir(Path::new("/crate/Cargo.toml"), dir.path()).unwrap(),
+                   Path::new("/crate"));
+
+        for manifest_path in &["Cargo.toml", "/"] {
+            let err = package_source_dir(Path::new(manifest_path), dir.path()).unwrap_err();
+            match err.downcast_ref::<MetadataError>() {
+                Some(&MetadataError::SourcePathUnavailable) => {}
+                _ => panic!("unexpected error: {}", err),
+            }
+        }
+
+        File::create(dir.path().join("Cargo.toml")).unwrap();
+        for manifest_path in &["Cargo.toml", "/"] {
+            assert_eq!(package_source_dir(Path::new(manifest_path), dir.path()).unwrap(),
+                       dir.path());
+        }
+    }
+
+    #[test]
+    fn test_from_source_dir_merges_orig() {
+        use std::fs::File;
+        use std::io::Write;
+
+        let packaged = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            features = [ "feature1" ]
+            no-deps = true
+        "#;
+        let orig = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            rustdoc-args = [ "--example-rustdoc-arg" ]
+            no-deps = false
+        "#;
+        let dir = tempdir::TempDir::new("cratesfyi").unwrap();
+        File::create(dir.path().join("Cargo.toml")).unwrap()
+            .write_all(packaged.as_bytes()).unwrap();
+        File::create(dir.path().join("Cargo.toml.orig")).unwrap()
+            .write_all(orig.as_bytes()).unwrap();
+
+        let metadata = Metadata::from_source_dir(dir.path(), None).unwrap();
+        assert_eq!(metadata.features, Some(vec!["feature1".to_owned()]));
+        assert_eq!(metadata.rustdoc_args, Some(vec!["--example-rustdoc-arg".to_owned()]));
+        assert!(!metadata.no_deps);
+    }
+
+    #[test]
+    fn test_from_source_dir_with_broken_manifest() {
+        use std::fs::File;
+        use std::io::Write;
+
+        let broken = "[package\nname = \"test";
+        let dir = tempdir::TempDir::new("cratesfyi").unwrap();
+        File::create(dir.path().join("Cargo.toml.orig")).unwrap()
+            .write_all(broken.as_bytes()).unwrap();
+        assert!(Metadata::from_source_dir(dir.path(), None).is_err());
+
+        File::create(dir.path().join("Cargo.toml")).unwrap()
+            .write_all(b"[package]\nname = \"test\"\n").unwrap();
+        assert!(!Metadata::from_source_dir(dir.path(), None).unwrap().all_features);
+
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            all-features = true
+        "#;
+        File::create(dir.path().join("Cargo.toml")).unwrap()
+            .write_all(manifest.as_bytes()).unwrap();
+        assert!(Metadata::from_source_dir(dir.path(), None).unwrap().all_features);
+
+        File::create(dir.path().join("Cargo.toml")).unwrap()
+            .write_all(broken.as_bytes()).unwrap();
+        File::create(dir.path().join("Cargo.toml.orig")).unwrap()
+            .write_all(manifest.as_bytes()).unwrap();
+        assert!(Metadata::from_source_dir(dir.path(), None).unwrap().all_features);
+    }
+
+    #[test]
+    fn test_from_source_dir_with_workspace() {
+        use std::fs::File;
+        use std::io::Write;
+
+        let workspace = r#"
+            [workspace]
+            members = [ "test" ]
+
+            [workspace.metadata.docs.rs]
+            all-features = true
+            rustdoc-args = [ "--cfg", "docsrs" ]
+        "#;
+
+        let dir = tempdir::TempDir::new("cratesfyi").unwrap();
+        File::create(dir.path().join("Cargo.toml")).unwrap()
+            .write_all(b"[package]\nname = \"test\"\n").unwrap();
+        let metadata = Metadata::from_source_dir(dir.path(), Some(workspace)).unwrap();
+        assert!(metadata.all_features);
+        assert_eq!(metadata.rustdoc_args.unwrap(), vec!["--cfg".to_owned(), "docsrs".to_owned()]);
+
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            default-target = "x86_64-pc-windows-msvc"
+            rustdoc-args = [ "--example-rustdoc-arg" ]
+        "#;
+        File::create(dir.path().join("Cargo.toml")).unwrap()
+            .write_all(manifest.as_bytes()).unwrap();
+        let metadata = Metadata::from_source_dir(dir.path(), Some(workspace)).unwrap();
+        assert!(metadata.all_features);
+        assert_eq!(metadata.default_target.unwrap(), "x86_64-pc-windows-msvc".to_owned());
+        assert_eq!(metadata.rustdoc_args.unwrap(), vec!["--example-rustdoc-arg".to_owned()]);
+
+        let metadata = Metadata::from_source_dir(dir.path(), Some("[workspace]")).unwrap();
+        assert!(!metadata.all_features);
+        assert_eq!(metadata.default_target.unwrap(), "x86_64-pc-windows-msvc".to_owned());
+    }
+
+    #[test]
+    fn test_find_package_dir() {
+        use std::fs::{self, File};
+        use std::io::Write;
+        use super::find_package_dir;
+
+        let dir = tempdir::TempDir::new("cratesfyi").unwrap();
+        let vendor_dir = dir.path().join("vendor");
+        let src_dir = vendor_dir.join("test").join("src");
+        fs::create_dir_all(&src_dir).unwrap();
+        File::create(dir.path().join("Cargo.toml")).unwrap()
+            .write_all(b"[package]\nname = \"test\"\n").unwrap();
+        File::create(vendor_dir.join("Cargo.toml")).unwrap()
+            .write_all(b"[workspace]\n").unwrap();
+        File::create(vendor_dir.join("test").join("Cargo.toml")).unwrap()
+            .write_all(b"[package]\nname = \"unrelated\"\n").unwrap();
+
+        assert_eq!(find_package_dir(&src_dir, "test"), Some(dir.path().to_path_buf()));
+        assert_eq!(find_package_dir(&src_dir, "unrelated"),
+                   Some(vendor_dir.join("test")));
+        assert_eq!(find_package_dir(&src_dir, "missing"), None);
+
+        let deep_dir = src_dir.join("a").join("b");
+        fs::create_dir_all(&deep_dir).unwrap();
+        assert_eq!(find_package_dir(&deep_dir, "test"), None);
+    }
+
+    #[test]
+    fn test_from_member_dir() {
+        use std::fs::{self, File};
+        use std::io::Write;
+
+        let workspace = r#"
+            [workspace]
+            members = [ "member" ]
+
+            [workspace.metadata.docs.rs]
+            all-features = true
+        "#;
+        let member = r#"
+            [package]
+            name = "member"
+
+            [package.metadata.docs.rs]
+            default-target = "x86_64-pc-windows-msvc"
+        "#;
+
+        let dir = tempdir::TempDir::new("cratesfyi").unwrap();
+        let member_dir = dir.path().join("member");
+        fs::create_dir(&member_dir).unwrap();
+        File::create(member_dir.join("Cargo.toml")).unwrap()
+            .write_all(member.as_bytes()).unwrap();
+
+        let metadata = Metadata::from_member_dir(&member_dir).unwrap();
+        assert!(!metadata.all_features);
+
+        File::create(dir.path().join("Cargo.toml")).unwrap()
+            .write_all(workspace.as_bytes()).unwrap();
+
+        let metadata = Metadata::from_member_dir(&member_dir).unwrap();
+        assert!(metadata.all_features);
+        assert_eq!(metadata.default_target.unwrap(), "x86_64-pc-windows-msvc".to_owned());
+
+        // crates under the workspace which are not members don't use its table
+        let other_dir = dir.path().join("other");
+        fs::create_dir(&other_dir).unwrap();
+        File::create(other_dir.join("Cargo.toml")).unwrap()
+            .write_all(member.as_bytes()).unwrap();
+        assert!(!Metadata::from_member_dir(&other_dir).unwrap().all_features);
+
+        let workspace = workspace.replace("[ \"member\" ]", "[ \"*\" ]\nexclude = [ \"other\" ]");
+        File::create(dir.path().join("Cargo.toml")).unwrap()
+            .write_all(workspace.as_bytes()).unwrap();
+        assert!(Metadata::from_member_dir(&member_dir).unwrap().all_features);
+        assert!(!Metadata::from_member_dir(&other_dir).unwrap().all_features);
+
+        // the workspace is only searched for a few levels
+        let deep_dir = dir.path().join("a").join("b").join("c").join("d");
+        fs::create_dir_all(&deep_dir).unwrap();
+        File::create(deep_dir.join("Cargo.toml")).unwrap()
+            .write_all(member.as_bytes()).unwrap();
+        let workspace = workspace.replace("[ \"*\" ]", "[ \"*\", \"a/b/c/d\" ]");
+        File::create(dir.path().join("Cargo.toml")).unwrap()
+            .write_all(workspace.as_bytes()).unwrap();
+        assert!(!Metadata::from_member_dir(&deep_dir).unwrap().all_features);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut metadata = Metadata::default();
+        metadata.default_target = Some("x86_64-apple-darwin".to_owned());
+        assert!(metadata.validate().is_ok());
+
+        metadata.targets = Some(vec!["wasm32-unknown-unknown".to_owned(),
+                                     "x86_64-apple-darwin".to_owned()]);
+        assert!(metadata.validate().is_ok());
+
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            default-target = "x86_64-apple-darwin"
+            targets = [ "wasm32-unknown-unknown", "x86_64-pc-windows-msvc" ]
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(metadata.validate().is_ok());
+        let warnings = metadata.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].to_string(),
+                   "default-target 'x86_64-apple-darwin' is not one of the targets, it's built \
+                    in addition to them");
+        assert_eq!(metadata.all_targets(), vec!["x86_64-apple-darwin".to_owned(),
+                                                "wasm32-unknown-unknown".to_owned(),
+                                                "x86_64-pc-windows-msvc".to_owned()]);
+    }
+
+    #[test]
+    fn test_validate_duplicate_targets() {
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            targets = [ "x86_64-apple-darwin", "wasm32-unknown-unknown", "x86_64-apple-darwin" ]
+        "#;
+        let err = Metadata::from_str(manifest).unwrap().validate().unwrap_err();
+        assert_eq!(err.to_string(), "targets has target `x86_64-apple-darwin` more than once");
+
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            exclude-targets = [ "wasm32-unknown-unknown", "wasm32-unknown-unknown" ]
+        "#;
+        let err = Metadata::from_str(manifest).unwrap().validate().unwrap_err();
+        assert_eq!(err.to_string(),
+                   "exclude-targets has target `wasm32-unknown-unknown` more than once");
+
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            targets = [ "x86_64-apple-darwin", "wasm32-unknown-unknown" ]
+            exclude-targets = [ "wasm32-unknown-unknown" ]
+        "#;
+        let err = Metadata::from_str(manifest).unwrap().validate().unwrap_err();
+        assert_eq!(err.to_string(),
+                   "target `wasm32-unknown-unknown` is in both targets and exclude-targets");
+
+        let manifest = r#"
+            [package.metadata.docs.rs]
+            targets = "*"
+            exclude-targets = [ "wasm32-unknown-unknown" ]
+        "#;
+        assert!(Metadata::from_str(manifest).unwrap().validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_all_features() {
+        let mut metadata = Metadata::default();
+        metadata.all_features = true;
+        assert!(metadata.validate().is_ok());
+
+        metadata.features = Some(Vec::new());
+        assert!(metadata.validate().is_ok());
+
+        metadata.features = Some(vec!["feature1".to_owned()]);
+        assert!(metadata.validate().is_err());
+        // crates with both keys built before, the builder only warns about them
+        assert!(metadata.validate_build().is_ok());
+        assert_eq!(metadata.warnings(), vec![MetadataWarning::Invalid {
+            key: "features".to_owned(),
+            reason: "all-features can't be combined with an explicit features list, features \
+                     are ignored when all-features is set".to_owned(),
+        }]);
+
+        metadata.all_features = false;
+        assert!(metadata.validate().is_ok());
+        assert!(metadata.warnings().is_empty());
+
+        let mut metadata = Metadata::default();
+        metadata.no_default_features = true;
+        assert!(metadata.warnings().is_empty());
+
+        metadata.all_features = true;
+        assert!(metadata.validate().is_ok());
+        assert_eq!(metadata.warnings(), vec![MetadataWarning::Redundant {
+            reason: "no-default-features is redundant when all-features is set".to_owned(),
+        }]);
+    }
+
+    #[test]
+    fn test_metadata_warning_display() {
+        let warning = MetadataWarning::UnknownKey {
+            key: "all-feature".to_owned(),
+            suggestion: Some("all-features".to_owned()),
+        };
+        assert_eq!(warning.to_string(), "unknown key 'all-feature'; did you mean 'all-features'?");
+
+        let warning = MetadataWarning::UnknownKey { key: "foo".to_owned(), suggestion: None };
+        assert_eq!(warning.to_string(), "unknown key 'foo'");
+
+        let warning = MetadataWarning::Redundant { reason: "foo is redundant".to_owned() };
+        assert_eq!(warning.to_string(), "foo is redundant");
+
+        let warning = MetadataWarning::Deprecated {
+            key: "foo".to_owned(),
+            replacement: "bar".to_owned(),
+        };
+        assert_eq!(warning.to_string(), "'foo' is deprecated, use 'bar' instead");
+
+        let warning = MetadataWarning::Invalid {
+            key: "foo".to_owned(),
+            reason: "foo is invalid".to_owned(),
+        };
+        assert_eq!(warning.to_string(), "foo is invalid");
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            all-features = true
+        "#;
+        assert!(Metadata::from_bytes(manifest.as_bytes()).unwrap().all_features);
+        assert!(Metadata::from_reader(manifest.as_bytes()).unwrap().all_features);
+
+        let invalid = b"[package]\nname = \"\xff\xfe\"\n";
+        let err = match Metadata::from_bytes(invalid) {
+            Ok(_) => panic!("invalid UTF-8 parsed successfully"),
+            Err(e) => e.to_string(),
+        };
+        assert!(err.contains("UTF-8"), "{}", err);
+        assert!(Metadata::from_reader(&invalid[..]).is_err());
+    }
+
+    #[test]
+    fn test_from_manifest_validates() {
+        use serde_json;
+        use std::fs::File;
+        use std::io::Write;
+
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            all-features = true
+            features = [ "feature1" ]
+        "#;
+        let value: serde_json::Value = serde_json::from_str(r#"{ "docs": { "rs": {
+            "all-features": true,
+            "features": [ "feature1" ]
+        } } }"#).unwrap();
+        let parsed = vec![Metadata::from_str(manifest).unwrap(),
+                          Metadata::from_bytes(manifest.as_bytes()).unwrap(),
+                          Metadata::from_reader(manifest.as_bytes()).unwrap(),
+                          Metadata::from_json(&value).unwrap()];
+        for metadata in parsed {
+            assert!(metadata.all_features);
+            assert!(metadata.validate().is_err());
+        }
+
+        let dir = tempdir::TempDir::new("cratesfyi").unwrap();
+        let path = dir.path().join("Cargo.toml");
+        File::create(&path).unwrap().write_all(manifest.as_bytes()).unwrap();
+        let err = Metadata::from_manifest(&path).unwrap_err();
+        assert!(err.to_string().contains("all-features"), "{}", err);
+
+        File::create(&path).unwrap().write_all(b"[package.metadata.docs.rs]\nno-deps = true")
+            .unwrap();
+        assert!(Metadata::from_manifest(&path).unwrap().no_deps);
+    }
+
+    #[test]
+    fn test_metadata_error() {
+        use super::MetadataError;
+
+        let dir = tempdir::TempDir::new("cratesfyi").unwrap();
+        match Metadata::from_member_dir(dir.path()) {
+            Ok(_) => panic!("metadata read without a manifest"),
+            Err(e) => match e.downcast_ref::<MetadataError>() {
+                Some(&MetadataError::ManifestNotFound) => {}
+                _ => panic!("unexpected error: {}", e),
+            },
+        }
+
+        match Metadata::from_str("[package.metadata.docs.rs]\nall-features = 1") {
+            Ok(_) => panic!("invalid value accepted"),
+            Err(e) => match e.downcast_ref::<MetadataError>() {
+                Some(&MetadataError::Parse(_)) => {}
+                _ => panic!("unexpected error: {}", e),
+            },
+        }
+
+        let mut metadata = Metadata::default();
+        metadata.default_target = Some("x86_64-linux".to_owned());
+        match metadata.validate_targets() {
+            Ok(_) => panic!("invalid target accepted"),
+            Err(e) => match e.downcast_ref::<MetadataError>() {
+                Some(&MetadataError::InvalidTarget(ref target)) => {
+                    assert_eq!(target, "x86_64-linux");
+                }
+                _ => panic!("unexpected error: {}", e),
+            },
+        }
+    }
+
+    #[test]
+    fn test_from_json() {
+        use serde_json;
+
+        let value: serde_json::Value = serde_json::from_str(r#"{
+            "docs": {
+                "rs": {
+                    "all-features": true,
+                    "targets": [ "x86_64-apple-darwin" ],
+                    "rustdoc-args": [ "--cfg", "docsrs" ]
+                }
+            },
+            "other-tool": { "key": "value" }
+        }"#).unwrap();
+        let metadata = Metadata::from_json(&value).unwrap();
+        assert!(metadata.all_features);
+        assert_eq!(metadata.targets, Some(vec!["x86_64-apple-darwin".to_owned()]));
+        assert_eq!(metadata.rustdoc_args,
+                   Some(vec!["--cfg".to_owned(), "docsrs".to_owned()]));
+
+        let metadata = Metadata::from_json(&serde_json::Value::Null).unwrap();
+        assert!(!metadata.all_features);
+
+        let value: serde_json::Value =
+            serde_json::from_str(r#"{ "docs": { "rs": { "all-features": 1 } } }"#).unwrap();
+        assert!(Metadata::from_json(&value).is_err());
+    }
+
+    #[test]
+    fn test_invalid_manifest() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs
+            all-features = true
+        "#;
+        let err = match Metadata::from_str(manifest) {
+            Ok(_) => panic!("invalid manifest parsed successfully"),
+            Err(e) => e.to_string(),
+        };
+        assert!(err.starts_with("Failed to parse manifest: "), "{}", err);
+        assert!(err.contains("at line 5"), "{}", err);
+    }
+
+    #[test]
+    fn test_invalid_value() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            all-features = "yes"
+        "#;
+        assert!(Metadata::from_str(manifest).is_err());
+
+        // TOML doesn't allow arrays with mixed types
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            features = [ "feature1", 2 ]
+        "#;
+        assert!(Metadata::from_str(manifest).is_err());
+    }
+
+    #[test]
+    fn test_non_string_entries() {
+        let value: serde_json::Value = serde_json::from_str(r#"{ "docs": { "rs": {
+            "features": [ "feature1", 2, "feature2" ],
+            "rustc-args": [ true, "--example-rustc-arg" ]
+        } } }"#).unwrap();
+        let metadata = Metadata::from_json(&value).unwrap();
+        assert_eq!(metadata.features, Some(vec!["feature1".to_owned(), "feature2".to_owned()]));
+        assert_eq!(metadata.rustc_args, None);
+        assert_eq!(metadata.skipped_entries,
+                   vec![("features".to_owned(), "2".to_owned()),
+                        ("rustc-args".to_owned(), "true".to_owned())]);
+        let warnings: Vec<String> = metadata.warnings().iter().map(|w| w.to_string()).collect();
+        assert_eq!(warnings, vec!["features entry 2 is not a string, ignoring it",
+                                  "rustc-args entry true is not a string, ignoring rustc-args"]);
+
+        let metadata = Metadata::from_str("[package.metadata.docs.rs]\nfeatures = [ 1 ]")
+            .unwrap();
+        assert_eq!(metadata.features, Some(Vec::new()));
+        assert_eq!(metadata.warnings().len(), 1);
+
+        let value: serde_json::Value = serde_json::from_str(r#"{ "docs": { "rs": {
+            "features": { "wasm32-unknown-unknown": [ "wasm", 1 ] },
+            "feature-matrix": [ [ "feature1", false ], [ "feature2" ] ],
+            "targets": { "x86_64-pc-windows-msvc": { "rustc-args": [ "--cfg", 2 ] } },
+            "x86_64-apple-darwin": { "rustdoc-args": [ 4.5, "--cfg", "mac" ] },
+            "nightly": { "rustdoc-args": [ "--cfg", "nightly", 3 ] }
+        } } }"#).unwrap();
+        let metadata = Metadata::from_json(&value).unwrap();
+        assert_eq!(metadata.target_features["wasm32-unknown-unknown"], vec!["wasm".to_owned()]);
+        assert_eq!(metadata.feature_matrix,
+                   Some(vec![vec!["feature1".to_owned()], vec!["feature2".to_owned()]]));
+        assert_eq!(metadata.target_overrides["x86_64-pc-windows-msvc"].rustc_args, None);
+        assert!(!metadata.per_target_rustdoc_args.contains_key("x86_64-apple-darwin"));
+        assert_eq!(metadata.channel_args["nightly"].rustdoc_args, None);
+        let keys: Vec<&str> = metadata.skipped_entries.iter().map(|e| e.0.as_str()).collect();
+        assert_eq!(keys, vec!["feature-matrix", "features.wasm32-unknown-unknown",
+                              "nightly.rustdoc-args", "targets.x86_64-pc-windows-msvc.rustc-args",
+                              "x86_64-apple-darwin.rustdoc-args"]);
+    }
+
+    #[test]
+    fn test_missing_metadata_table() {
+        let manifest = r#"
+            [package]
+            name = "test"
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(metadata.features.is_none());
+        assert!(!metadata.all_features);
+        assert!(metadata.default_target.is_none());
     }
 }
@@ -28,12 +28,16 @@ extern crate badge;
 extern crate crates_index_diff;
 extern crate git2;
 extern crate toml;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
 extern crate html5ever;
 
 pub use self::docbuilder::DocBuilder;
 pub use self::docbuilder::ChrootBuilderResult;
 pub use self::docbuilder::options::DocBuilderOptions;
-pub use self::docbuilder::metadata::Metadata;
+pub use self::docbuilder::metadata::{Metadata, MetadataBuilder};
 pub use self::web::start_web_server;
 
 pub mod error;
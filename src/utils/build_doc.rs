@@ -4,7 +4,10 @@
 //! documentation of a crate and not installing anything.
 
 use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
 use std::env;
+use std::fs;
 use std::sync::Arc;
 
 use cargo::core::{self, SourceId, Dependency, Source, Package, Workspace};
@@ -17,10 +20,11 @@ use cargo::util::{CargoResult, Config, internal, Filesystem};
 use cargo::sources::SourceConfigMap;
 use cargo::ops::{self, Packages};
 
-use utils::{get_current_versions, parse_rustc_version};
+use utils::{get_current_versions, parse_rustc_version, parse_rustc_channel};
 use error::Result;
 
 use Metadata;
+use docbuilder::metadata::HOST_TARGET;
 
 
 /// Builds documentation of a crate and version.
@@ -59,14 +63,60 @@ pub fn build_doc(name: &str, vers: Option<&str>, target: Option<&str>) -> Result
     let current_dir = try!(env::current_dir());
     let target_dir = PathBuf::from(current_dir).join("cratesfyi");
 
-    let metadata = Metadata::from_package(&pkg).map_err(|e| internal(e.to_string()))?;
+    let mut metadata = Metadata::from_package(&pkg).map_err(|e| internal(e.to_string()))?;
+
+    let crate_features: Vec<String> = pkg.summary().features().keys()
+        .map(|f| f.to_string())
+        .collect();
+
+    for line in metadata.summary().lines() {
+        info!("[package.metadata.docs.rs]: {}", line);
+    }
+    for warning in metadata.warnings() {
+        warn!("[package.metadata.docs.rs]: {}", warning);
+    }
+    for warning in metadata.features_warnings(&crate_features) {
+        warn!("[package.metadata.docs.rs]: {}", warning);
+    }
+    metadata.sanitize_args()?;
+    if let Some(path) = source_path(&pkg) {
+        metadata.resolve_rustdoc_paths(path)?;
+    }
+
+    let rustdoc_header = match source_path(&pkg) {
+        Some(path) => metadata.rustdoc_header_path(path)?,
+        None => None,
+    };
+    let extra_files = match source_path(&pkg) {
+        Some(path) => metadata.extra_files_paths(path)?,
+        None => Vec::new(),
+    };
+    let rustdoc_map_args = metadata.rustdoc_map_args();
+
+    // target overrides, per-target rustdoc-args and channel arguments only apply to this build
+    let rustc_version = get_current_versions()?.0;
+    let build_metadata = metadata.resolve_build(target.unwrap_or(HOST_TARGET),
+                                                parse_rustc_channel(&rustc_version));
+    let package_rustdoc_args = build_metadata.default_rustdoc_args();
+    let all_features = metadata.uses_all_features();
+    let document_examples = metadata.document_examples;
+    let (no_default_features, features) = if metadata.features_doc.is_some() {
+        metadata.effective_features()
+    } else {
+        let mut target_metadata = metadata.clone();
+        target_metadata.features = metadata.features_for(target.unwrap_or(HOST_TARGET));
+        (metadata.no_default_features, target_metadata.expand_features(&crate_features))
+    };
+
+    // variables are only set for this build, they are restored when _env is dropped
+    let _env = metadata.env.as_ref().map(EnvGuard::set);
 
     // This is only way to pass rustc_args to cargo.
     // CompileOptions::target_rustc_args is used only for the current crate,
     // and since docs.rs never runs rustc on the current crate, we assume rustc_args
     // will be used for the dependencies. That is why we are creating RUSTFLAGS environment
     // variable instead of using target_rustc_args.
-    if let Some(rustc_args) = metadata.rustc_args {
+    if let Some(ref rustc_args) = build_metadata.rustc_args {
         env::set_var("RUSTFLAGS", rustc_args.join(" "));
     }
 
@@ -75,7 +125,7 @@ pub fn build_doc(name: &str, vers: Option<&str>, target: Option<&str>) -> Result
     let mut rustdoc_args: Vec<String> =
         vec!["-Z".to_string(), "unstable-options".to_string(),
              "--resource-suffix".to_string(),
-             format!("-{}", parse_rustc_version(get_current_versions()?.0)?),
+             format!("-{}", parse_rustc_version(&rustc_version)?),
              "--static-root-path".to_string(), "/".to_string(),
              "--disable-per-crate-search".to_string()];
 
@@ -88,10 +138,15 @@ pub fn build_doc(name: &str, vers: Option<&str>, target: Option<&str>) -> Result
                                   name.replace("-", "_"), dep.name(), dep.version()));
     }
 
-    if let Some(package_rustdoc_args) = metadata.rustdoc_args {
-        rustdoc_args.append(&mut package_rustdoc_args.iter().map(|s| s.to_owned()).collect());
+    if let Some(rustdoc_header) = rustdoc_header {
+        rustdoc_args.push("--html-in-header".to_string());
+        rustdoc_args.push(rustdoc_header.to_string_lossy().into_owned());
     }
 
+    rustdoc_args.extend(package_rustdoc_args);
+
+    rustdoc_args.extend(rustdoc_map_args);
+
     let mut build_config = try!(BuildConfig::new(&config,
                                                  None,
                                                  &target.map(|t| t.to_string()),
@@ -102,14 +157,14 @@ pub fn build_doc(name: &str, vers: Option<&str>, target: Option<&str>) -> Result
     let opts = ops::CompileOptions {
         config: &config,
         build_config,
-        features: metadata.features.unwrap_or(Vec::new()),
-        all_features: metadata.all_features,
-        no_default_features: metadata.no_default_features,
+        features: features,
+        all_features: all_features,
+        no_default_features: no_default_features,
         spec: Packages::Packages(Vec::new()),
         filter: ops::CompileFilter::new(true,
                                         Vec::new(), false,
                                         Vec::new(), false,
-                                        Vec::new(), false,
+                                        Vec::new(), document_examples,
                                         Vec::new(), false,
                                         false),
         target_rustc_args: None,
@@ -118,14 +173,78 @@ pub fn build_doc(name: &str, vers: Option<&str>, target: Option<&str>) -> Result
         export_dir: None,
     };
 
+    let doc_dir = match target {
+        Some(target) => target_dir.join(target).join("doc"),
+        None => target_dir.join("doc"),
+    };
+
     let ws = try!(Workspace::ephemeral(pkg, &config, Some(Filesystem::new(target_dir)), false));
     let exec: Arc<Executor> = Arc::new(DefaultExecutor);
     let source = try!(source_cfg_map.load(source_id));
     try!(ops::compile_ws(&ws, Some(source), &opts, &exec));
 
+    // copy extra-files next to the generated documentation, without replacing files rustdoc
+    // generated, like index.html or search-index.js
+    for path in extra_files {
+        if let Some(file_name) = path.file_name() {
+            let dest = doc_dir.join(file_name);
+            if dest.exists() {
+                return Err(format_err!("extra-files `{}` would overwrite a documentation file",
+                                       file_name.to_string_lossy()));
+            }
+            try!(fs::copy(&path, dest));
+        }
+    }
+
+    if metadata.max_output_size.is_some() {
+        metadata.check_output_size(try!(dir_size(&doc_dir)))?;
+    }
+
     Ok(try!(ws.current()).clone())
 }
 
+/// Sets environment variables and restores their previous values when dropped.
+struct EnvGuard {
+    previous: Vec<(String, Option<OsString>)>,
+}
+
+impl EnvGuard {
+    fn set(vars: &BTreeMap<String, String>) -> EnvGuard {
+        let previous = vars.iter().map(|(key, value)| {
+            let previous = env::var_os(key);
+            env::set_var(key, value);
+            (key.clone(), previous)
+        }).collect();
+        EnvGuard { previous: previous }
+    }
+}
+
+impl Drop for EnvGuard {
+    fn drop(&mut self) {
+        for (key, value) in self.previous.drain(..) {
+            match value {
+                Some(value) => env::set_var(key, value),
+                None => env::remove_var(key),
+            }
+        }
+    }
+}
+
+/// Returns total size of the files in a directory, including its subdirectories.
+fn dir_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in try!(fs::read_dir(path)) {
+        let entry = try!(entry);
+        let file_type = try!(entry.file_type());
+        if file_type.is_dir() {
+            size += try!(dir_size(&entry.path()));
+        } else if file_type.is_file() {
+            size += try!(entry.metadata()).len();
+        }
+    }
+    Ok(size)
+}
+
 fn resolve_deps<'cfg>(pkg: &Package, config: &'cfg Config, src: Box<Source + 'cfg>)
     -> CargoResult<Vec<(String, Package)>>
 {
@@ -237,4 +356,22 @@ mod test {
         assert!(src_path.exists());
         assert!(src_path.is_dir());
     }
+
+
+    #[test]
+    fn test_env_guard() {
+        env::set_var("CRATESFYI_TEST_EXISTING", "existing");
+        env::remove_var("CRATESFYI_TEST_NEW");
+
+        let mut vars = BTreeMap::new();
+        vars.insert("CRATESFYI_TEST_EXISTING".to_owned(), "changed".to_owned());
+        vars.insert("CRATESFYI_TEST_NEW".to_owned(), "new".to_owned());
+        {
+            let _env = EnvGuard::set(&vars);
+            assert_eq!(env::var("CRATESFYI_TEST_EXISTING").unwrap(), "changed");
+            assert_eq!(env::var("CRATESFYI_TEST_NEW").unwrap(), "new");
+        }
+        assert_eq!(env::var("CRATESFYI_TEST_EXISTING").unwrap(), "existing");
+        assert!(env::var_os("CRATESFYI_TEST_NEW").is_none());
+    }
 }
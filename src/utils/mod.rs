@@ -6,7 +6,8 @@ pub use self::copy::{copy_dir, copy_doc_dir};
 pub use self::github_updater::github_updater;
 pub use self::release_activity_updater::update_release_activity;
 pub use self::daemon::start_daemon;
-pub use self::rustc_version::{parse_rustc_version, get_current_versions, command_result};
+pub use self::rustc_version::{parse_rustc_version, parse_rustc_channel, get_current_versions,
+                              command_result};
 pub use self::html::extract_head_and_body;
 
 mod github_updater;
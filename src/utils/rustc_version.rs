@@ -19,6 +19,19 @@ pub fn parse_rustc_version<S: AsRef<str>>(version: S) -> Result<String> {
 }
 
 
+/// Parses the release channel, `stable`, `beta` or `nightly`, from rustc version string
+pub fn parse_rustc_channel<S: AsRef<str>>(version: S) -> &'static str {
+    let version = version.as_ref();
+    if version.contains("-nightly") || version.contains("-dev") {
+        "nightly"
+    } else if version.contains("-beta") {
+        "beta"
+    } else {
+        "stable"
+    }
+}
+
+
 /// Returns current version of rustc and cratesfyi
 pub fn get_current_versions() -> Result<(String, String)> {
     let rustc_version = command_result(Command::new("rustc").arg("--version").output()?)?;
@@ -45,3 +58,11 @@ fn test_parse_rustc_version() {
     assert_eq!(parse_rustc_version("cratesfyi 0.2.0 (ba9ae23 2016-05-26)").unwrap(),
                "20160526-0.2.0-ba9ae23");
 }
+
+
+#[test]
+fn test_parse_rustc_channel() {
+    assert_eq!(parse_rustc_channel("rustc 1.10.0-nightly (57ef01513 2016-05-23)"), "nightly");
+    assert_eq!(parse_rustc_channel("rustc 1.30.0-beta.15 (590121930 2018-10-12)"), "beta");
+    assert_eq!(parse_rustc_channel("rustc 1.29.2 (17a9dc751 2018-10-05)"), "stable");
+}